@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use mongodb::bson;
+use tokio::io::AsyncWriteExt;
+
+use crate::{RepairDiff, RepairError};
+
+/// Number of audit records inserted at once.
+const BATCH_SIZE: usize = 100;
+
+/// Inserts the changes of repaired documents into an audit collection in batches,
+/// for [`RepairOptions::audit_collection`](crate::RepairOptions::audit_collection).
+pub(crate) struct AuditLog {
+    collection: mongodb::Collection<bson::Document>,
+    dry_run: bool,
+    records: Vec<bson::Document>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(collection: mongodb::Collection<bson::Document>, dry_run: bool) -> Self {
+        Self {
+            collection,
+            dry_run,
+            records: Vec::new(),
+        }
+    }
+
+    /// Queue the `changes` of a repaired document, inserting the batch once it is full.
+    pub(crate) async fn push(&mut self, changes: &RepairDiff) -> crate::Result<()> {
+        let mut record = bson::to_document(changes)?;
+        record.insert("repairedAt", bson::DateTime::now());
+        record.insert("dryRun", self.dry_run);
+        self.records.push(record);
+        if self.records.len() < BATCH_SIZE {
+            return Ok(());
+        }
+        self.flush().await
+    }
+
+    /// Insert the queued records.
+    pub(crate) async fn flush(&mut self) -> crate::Result<()> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        let records = std::mem::take(&mut self.records);
+        self.collection.insert_many(records).await?;
+        Ok(())
+    }
+}
+
+/// Writes the changes of repaired documents to a JSON Lines file, shared by
+/// concurrently repaired collections, in the format of the records of an
+/// audit collection, so that it can be replayed with [`read_audit_log`](crate::read_audit_log).
+///
+/// With a size limit, the file is renamed to `<name>.<timestamp>.json` once it
+/// exceeds the limit, and a new file is started.
+#[derive(Debug)]
+pub struct AuditFile {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    keep: Option<usize>,
+    file: tokio::sync::Mutex<tokio::fs::File>,
+    /// Handle of the current file, synced to disk by [`AuditFile::sync`].
+    sync_handle: Mutex<std::fs::File>,
+}
+
+impl AuditFile {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            max_bytes: None,
+            keep: None,
+            sync_handle: Mutex::new(file.try_clone()?),
+            file: tokio::sync::Mutex::new(tokio::fs::File::from_std(file)),
+        })
+    }
+
+    /// Rotate the file once it exceeds `max_bytes`.
+    #[must_use]
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Delete the oldest rotated files when more than `keep` exist.
+    #[must_use]
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = Some(keep);
+        self
+    }
+
+    /// Append the `changes` of a repaired document, rotating the file if it grew too large.
+    pub(crate) async fn write(&self, changes: &RepairDiff, dry_run: bool) -> crate::Result<()> {
+        let mut record = serde_json::to_value(changes)?;
+        if let Some(record) = record.as_object_mut() {
+            record.insert(
+                "repairedAt".to_string(),
+                bson::DateTime::now()
+                    .try_to_rfc3339_string()
+                    .map_err(|err| RepairError::Other(err.to_string()))?
+                    .into(),
+            );
+            record.insert("dryRun".to_string(), dry_run.into());
+        }
+        let mut file = self.file.lock().await;
+        file.write_all(format!("{record}\n").as_bytes()).await?;
+        file.flush().await?;
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(());
+        };
+        if file.metadata().await?.len() <= max_bytes {
+            return Ok(());
+        }
+        let rotated = self.rotated_path(bson::DateTime::now().timestamp_millis());
+        tokio::fs::rename(&self.path, &rotated).await?;
+        let new_file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let sync_handle = new_file.try_clone().await?.into_std().await;
+        *self
+            .sync_handle
+            .lock()
+            .map_err(|_| RepairError::Other("audit file is poisoned".to_string()))? = sync_handle;
+        *file = new_file;
+        eprintln!("rotated audit file to {}", rotated.display());
+        self.delete_old().await
+    }
+
+    /// Sync the written records to disk.
+    ///
+    /// Records are flushed as they are written, so this only blocks on the disk.
+    pub fn sync(&self) -> crate::Result<()> {
+        self.sync_handle
+            .lock()
+            .map_err(|_| RepairError::Other("audit file is poisoned".to_string()))?
+            .sync_all()?;
+        Ok(())
+    }
+
+    /// Path of the file rotated at `timestamp`, in milliseconds since the epoch.
+    fn rotated_path(&self, timestamp: i64) -> PathBuf {
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        self.path.with_file_name(format!("{stem}.{timestamp}.json"))
+    }
+
+    /// Delete the oldest rotated files beyond [`AuditFile::keep`].
+    async fn delete_old(&self) -> crate::Result<()> {
+        let Some(keep) = self.keep else {
+            return Ok(());
+        };
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut rotated: Vec<(i64, PathBuf)> = Vec::new();
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let timestamp = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(stem.as_ref()))
+                .and_then(|name| name.strip_prefix('.'))
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|timestamp| timestamp.parse().ok());
+            if let Some(timestamp) = timestamp {
+                rotated.push((timestamp, entry.path()));
+            }
+        }
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(keep);
+        for (_, path) in rotated.into_iter().take(excess) {
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn audit_file_rotates_and_syncs() {
+        let dir = std::env::temp_dir().join(format!("audit-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.json");
+        let audit_file = AuditFile::open(&path).unwrap().max_bytes(1).keep(1);
+        let changes = RepairDiff {
+            collection: "users".to_string(),
+            id: "0".repeat(24),
+            field_changes: Vec::new(),
+        };
+        for _ in 0..3 {
+            audit_file.write(&changes, true).await.unwrap();
+            // rotated files are named by millisecond
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        audit_file.sync().unwrap();
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert!(names.contains(&"audit.json".to_string()));
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Instant, SystemTime};
+
+use crate::RepairError;
+
+/// How often the blocklist file is checked for changes.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Dot-notation field paths excluded from the repair, loaded from a file
+/// with one path per line and `#` comments.
+///
+/// The file is reloaded when its modification time changes.
+#[derive(Debug)]
+pub struct FieldBlocklist {
+    path: PathBuf,
+    state: RwLock<BlocklistState>,
+}
+
+#[derive(Debug)]
+struct BlocklistState {
+    fields: HashSet<String>,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+/// Parse the field paths of a blocklist file.
+fn parse_fields(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+impl FieldBlocklist {
+    /// Load the blocklist file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let modified = std::fs::metadata(&path)?.modified().ok();
+        let fields = parse_fields(&std::fs::read_to_string(&path)?);
+        Ok(Self {
+            path,
+            state: RwLock::new(BlocklistState {
+                fields,
+                modified,
+                last_check: Instant::now(),
+            }),
+        })
+    }
+
+    /// Whether the field at `path` is excluded.
+    ///
+    /// Array items (`field[idx]`) match the path of their array.
+    pub(crate) fn contains(&self, path: &str) -> bool {
+        let Ok(state) = self.state.read() else {
+            return false;
+        };
+        if state.fields.contains(path) {
+            return true;
+        }
+        if !path.contains('[') {
+            return false;
+        }
+        let mut normalized = String::with_capacity(path.len());
+        let mut in_index = false;
+        for c in path.chars() {
+            match c {
+                '[' => in_index = true,
+                ']' => in_index = false,
+                c if !in_index => normalized.push(c),
+                _ => {}
+            }
+        }
+        state.fields.contains(&normalized)
+    }
+
+    /// Reload the file if it changed, checking at most once per second.
+    pub(crate) fn reload_if_changed(&self) -> crate::Result<()> {
+        if self
+            .state
+            .read()
+            .map_or(true, |state| state.last_check.elapsed() < CHECK_INTERVAL)
+        {
+            return Ok(());
+        }
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| RepairError::Other("field blocklist is poisoned".to_string()))?;
+        state.last_check = Instant::now();
+        let modified = std::fs::metadata(&self.path)?.modified().ok();
+        if modified == state.modified {
+            return Ok(());
+        }
+        state.fields = parse_fields(&std::fs::read_to_string(&self.path)?);
+        state.modified = modified;
+        eprintln!(
+            "reloaded {} blocked fields from {}",
+            state.fields.len(),
+            self.path.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+
+/// What a [`FieldChange`] repaired.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKind {
+    /// The string value of the field.
+    #[default]
+    Value,
+    /// The name of the field.
+    Key,
+    /// The field was moved from the old to the new path, like `$rename`.
+    Rename,
+    /// A legacy UUID (binary subtype 3) was converted to a standard UUID (subtype 4).
+    Uuid,
+}
+
+impl ChangeKind {
+    fn is_value(&self) -> bool {
+        *self == Self::Value
+    }
+}
+
+/// A single repaired string value or field name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldChange {
+    /// Dot-notation path of the field, with array items as `field[idx]`.
+    #[serde(rename = "field")]
+    pub path: String,
+    /// Lossy UTF-8 rendering of the original value.
+    #[serde(rename = "old")]
+    pub old_value: String,
+    /// Repaired value.
+    #[serde(rename = "new")]
+    pub new_value: String,
+    #[serde(default, skip_serializing_if = "ChangeKind::is_value")]
+    pub kind: ChangeKind,
+    /// Hex encoding of the original bytes, for [`RepairOptions::report_raw_bytes`](crate::RepairOptions::report_raw_bytes).
+    #[serde(default, rename = "raw", skip_serializing_if = "Option::is_none")]
+    pub raw_hex: Option<String>,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ChangeKind::Value => write!(
+                f,
+                "[{}] {:?} => {:?}",
+                self.path, self.old_value, self.new_value
+            ),
+            ChangeKind::Key => write!(
+                f,
+                "[{}] field name {:?} => {:?}",
+                self.path, self.old_value, self.new_value
+            ),
+            ChangeKind::Rename => write!(f, "[{}] renamed from {}", self.new_value, self.old_value),
+            ChangeKind::Uuid => write!(
+                f,
+                "[{}] legacy UUID {} => UUID {}",
+                self.path, self.old_value, self.new_value
+            ),
+        }
+    }
+}
+
+/// All repaired values of a single document.
+///
+/// Displays as one line per changed field:
+///
+/// ```
+/// use mongo_repair_utf8::{ChangeKind, FieldChange, RepairDiff};
+///
+/// let diff = RepairDiff {
+///     collection: "users".to_string(),
+///     id: "65a000000000000000000000".to_string(),
+///     field_changes: vec![FieldChange {
+///         path: "name".to_string(),
+///         old_value: "caf\u{fffd}".to_string(),
+///         new_value: "café".to_string(),
+///         kind: ChangeKind::Value,
+///         raw_hex: None,
+///     }],
+/// };
+/// let text = diff.to_string();
+/// assert!(text.starts_with("collection = users"));
+/// assert!(text.ends_with("  [name] \"caf\u{fffd}\" => \"café\"\n"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepairDiff {
+    pub collection: String,
+    /// Hex `_id` of the document.
+    pub id: String,
+    #[serde(rename = "changes")]
+    pub field_changes: Vec<FieldChange>,
+}
+
+impl fmt::Display for RepairDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "collection = {: <20} id = {: <30}",
+            self.collection, self.id
+        )?;
+        for change in &self.field_changes {
+            writeln!(f, "  {change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert the `field[idx]` array items of `path` to the `field.idx` notation of update operators.
+fn update_path(path: &str) -> String {
+    path.replace('[', ".").replace(']', "")
+}
+
+/// Apply previously exported changes to the documents of `db` using `$set`.
+///
+/// Returns the number of documents that were updated.
+pub async fn apply_changes(db: &mongodb::Database, changes: &[RepairDiff]) -> crate::Result<u64> {
+    let mut updated = 0;
+    for document in changes {
+        let Ok(id) = bson::oid::ObjectId::parse_str(&document.id) else {
+            eprintln!(
+                "collection = {: <20} id = {: <30} SKIPPED (not an object id)",
+                document.collection, document.id
+            );
+            continue;
+        };
+        if document
+            .field_changes
+            .iter()
+            .any(|change| change.kind == ChangeKind::Key)
+        {
+            eprintln!(
+                "collection = {: <20} id = {: <30} repaired field names cannot be applied with $set",
+                document.collection, document.id
+            );
+        }
+        let result = db
+            .collection::<bson::Document>(&document.collection)
+            .update_one(bson::doc! {"_id": id}, update_document(document))
+            .await?;
+        if result.matched_count == 0 {
+            eprintln!(
+                "collection = {: <20} id = {: <30} NOT FOUND",
+                document.collection, document.id
+            );
+            continue;
+        }
+        updated += result.modified_count;
+        println!(
+            "collection = {: <20} id = {: <30} UPDATED",
+            document.collection, document.id
+        );
+    }
+    Ok(updated)
+}
+
+/// The update applying the changes of `document`, with `$set` and `$rename`.
+pub(crate) fn update_document(document: &RepairDiff) -> bson::Document {
+    let rename: bson::Document = document
+        .field_changes
+        .iter()
+        .filter(|change| change.kind == ChangeKind::Rename)
+        .map(|change| {
+            (
+                update_path(&change.old_value),
+                bson::Bson::from(update_path(&change.new_value)),
+            )
+        })
+        .collect();
+    let set: bson::Document = document
+        .field_changes
+        .iter()
+        .filter(|change| change.kind == ChangeKind::Value)
+        .map(|change| {
+            (
+                update_path(&change.path),
+                bson::Bson::from(change.new_value.as_str()),
+            )
+        })
+        .chain(
+            document
+                .field_changes
+                .iter()
+                .filter(|change| change.kind == ChangeKind::Uuid)
+                .filter_map(|change| {
+                    let uuid = bson::Uuid::parse_str(&change.new_value).ok()?;
+                    Some((
+                        update_path(&change.path),
+                        bson::Bson::Binary(bson::Binary::from_uuid(uuid)),
+                    ))
+                }),
+        )
+        .collect();
+    let mut update = bson::doc! {"$set": set};
+    if !rename.is_empty() {
+        update.insert("$rename", rename);
+    }
+    update
+}
+
+/// Read the changes of an [`AuditFile`](crate::AuditFile), or of an audit log exported as JSON Lines
+/// (e.g. with `mongoexport` from [`RepairOptions::audit_collection`](crate::RepairOptions::audit_collection)).
+///
+/// Changes of the same document are merged in the order of the log:
+///
+/// ```
+/// use mongo_repair_utf8::read_audit_log;
+///
+/// let log = r#"{"collection":"users","id":"65a000000000000000000000","changes":[{"field":"name","old":"caf�","new":"café"}],"dryRun":false}
+/// {"collection":"users","id":"65a000000000000000000000","changes":[{"field":"city","old":"K�ln","new":"Köln"}],"dryRun":false}
+/// "#;
+/// let changes = read_audit_log(log.as_bytes()).unwrap();
+/// assert_eq!(changes.len(), 1);
+/// assert_eq!(changes[0].field_changes.len(), 2);
+/// ```
+pub fn read_audit_log(reader: impl std::io::BufRead) -> crate::Result<Vec<RepairDiff>> {
+    let mut documents: Vec<RepairDiff> = Vec::new();
+    let mut index: std::collections::HashMap<(String, String), usize> =
+        std::collections::HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: RepairDiff = serde_json::from_str(&line)?;
+        let key = (record.collection.clone(), record.id.clone());
+        match index.get(&key) {
+            Some(&idx) => documents[idx].field_changes.extend(record.field_changes),
+            None => {
+                index.insert(key, documents.len());
+                documents.push(record);
+            }
+        }
+    }
+    Ok(documents)
+}
+
+/// The value at the dot-notation `path` of `doc`.
+fn lookup_path<'a>(doc: &'a bson::Document, path: &str) -> Option<&'a bson::Bson> {
+    let mut segments = path.split('.');
+    let mut value = doc.get(segments.next()?)?;
+    for segment in segments {
+        value = match value {
+            bson::Bson::Document(doc) => doc.get(segment)?,
+            bson::Bson::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Apply the changes of an audit log to the documents of `db` using `$set`,
+/// like [`apply_changes`].
+///
+/// A document is only updated if the current values of all its repaired
+/// fields still match the `old_value` recorded in the log, otherwise it is
+/// skipped as a conflict.
+///
+/// Returns the number of documents that were updated.
+pub async fn replay_changes(db: &mongodb::Database, changes: &[RepairDiff]) -> crate::Result<u64> {
+    let mut updated = 0;
+    for document in changes {
+        let Ok(id) = bson::oid::ObjectId::parse_str(&document.id) else {
+            eprintln!(
+                "collection = {: <20} id = {: <30} SKIPPED (not an object id)",
+                document.collection, document.id
+            );
+            continue;
+        };
+        let Some(current) = db
+            .collection::<bson::RawDocumentBuf>(&document.collection)
+            .find_one(bson::doc! {"_id": id})
+            .await?
+        else {
+            eprintln!(
+                "collection = {: <20} id = {: <30} NOT FOUND",
+                document.collection, document.id
+            );
+            continue;
+        };
+        let current = bson::Document::from_reader_utf8_lossy(current.as_bytes())?;
+        let conflict = document
+            .field_changes
+            .iter()
+            .filter(|change| change.kind == ChangeKind::Value)
+            .find(|change| {
+                lookup_path(&current, &update_path(&change.path)).and_then(bson::Bson::as_str)
+                    != Some(change.old_value.as_str())
+            });
+        if let Some(change) = conflict {
+            eprintln!(
+                "collection = {: <20} id = {: <30} CONFLICT ([{}] no longer matches {:?})",
+                document.collection, document.id, change.path, change.old_value
+            );
+            continue;
+        }
+        updated += apply_changes(db, std::slice::from_ref(document)).await?;
+    }
+    Ok(updated)
+}
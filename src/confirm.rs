@@ -0,0 +1,71 @@
+use std::fmt;
+
+use dialoguer::Confirm;
+
+use crate::RepairError;
+
+/// Decides whether a repaired value is written.
+///
+/// Without prompting, repairs can be checked like this:
+///
+/// ```
+/// use mongo_repair_utf8::testing::make_corrupt_doc;
+/// use mongo_repair_utf8::{fix_document, NeverConfirm, RepairOptions};
+/// use mongodb::bson;
+///
+/// let doc = make_corrupt_doc("name", b"caf\xe9");
+/// let options = RepairOptions::default().confirmation(NeverConfirm).build();
+/// let mut new_doc = bson::RawDocumentBuf::new();
+/// let changes = fix_document(&doc, &mut new_doc, &options, &mut Vec::new()).unwrap();
+/// assert!(changes.is_empty());
+/// ```
+pub trait ConfirmationStrategy: fmt::Debug + Send + Sync {
+    /// Whether the change described by `prompt` should be applied.
+    fn confirm(&self, prompt: &str) -> crate::Result<bool>;
+}
+
+/// Apply every change.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysConfirm;
+
+impl ConfirmationStrategy for AlwaysConfirm {
+    fn confirm(&self, _prompt: &str) -> crate::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Reject every change.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NeverConfirm;
+
+impl ConfirmationStrategy for NeverConfirm {
+    fn confirm(&self, _prompt: &str) -> crate::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Ask on the terminal before each change.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InteractiveConfirm {
+    /// Answer used when the terminal cannot be read (e.g. on EOF).
+    /// Without a default, such failures are returned as errors.
+    pub default: Option<bool>,
+}
+
+impl ConfirmationStrategy for InteractiveConfirm {
+    fn confirm(&self, prompt: &str) -> crate::Result<bool> {
+        match Confirm::new().with_prompt(prompt).interact() {
+            Ok(confirmed) => Ok(confirmed),
+            Err(err) => match self.default {
+                Some(default) => {
+                    eprintln!("interactive confirmation failed ({err}), assuming {default}");
+                    Ok(default)
+                }
+                None => {
+                    eprintln!("interactive confirmation failed ({err})");
+                    Err(RepairError::ConfirmationAborted)
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::RepairOptions;
+
+/// What to do with a repaired value that violates the
+/// [`RepairOptions::field_min_lengths`] or [`RepairOptions::field_max_lengths`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ConstraintAction {
+    /// Keep the original value.
+    #[default]
+    Skip,
+    /// Shorten values that are too long to the maximum length (values that are too short are skipped).
+    Truncate,
+    /// Fail the document.
+    Error,
+}
+
+impl ConstraintAction {
+    /// All supported actions.
+    pub const ALL: [ConstraintAction; 3] = [
+        ConstraintAction::Skip,
+        ConstraintAction::Truncate,
+        ConstraintAction::Error,
+    ];
+
+    /// Human-readable name, as accepted by [`FromStr`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Skip => "skip",
+            Self::Truncate => "truncate",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for ConstraintAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ConstraintAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|action| action.name()).collect();
+                format!(
+                    "unknown constraint violation action {s:?} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A repaired value whose length in characters is out of the bounds of its field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LengthViolation {
+    TooShort { len: usize, min: usize },
+    TooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for LengthViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { len, min } => {
+                write!(f, "repaired value has {len} characters, fewer than {min}")
+            }
+            Self::TooLong { len, max } => {
+                write!(f, "repaired value has {len} characters, more than {max}")
+            }
+        }
+    }
+}
+
+/// Check the repaired `value` of the field at `path` against the length constraints of `options`.
+pub(crate) fn length_violation(
+    path: &str,
+    value: &str,
+    options: &RepairOptions,
+) -> Option<LengthViolation> {
+    if options.field_min_lengths.is_empty() && options.field_max_lengths.is_empty() {
+        return None;
+    }
+    let len = value.chars().count();
+    let bound = |bounds: &[(String, usize)]| {
+        bounds
+            .iter()
+            .find(|(field, _)| field == path)
+            .map(|(_, bound)| *bound)
+    };
+    if let Some(min) = bound(&options.field_min_lengths).filter(|min| len < *min) {
+        return Some(LengthViolation::TooShort { len, min });
+    }
+    if let Some(max) = bound(&options.field_max_lengths).filter(|max| len > *max) {
+        return Some(LengthViolation::TooLong { len, max });
+    }
+    None
+}
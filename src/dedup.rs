@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::RepairError;
+
+/// What to do with a document whose repaired value collides with the repaired
+/// value of another document, for [`RepairOptions::deduplicate_repaired`].
+///
+/// [`RepairOptions::deduplicate_repaired`]: crate::RepairOptions::deduplicate_repaired
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DedupAction {
+    /// Do not write the document.
+    #[default]
+    Skip,
+    /// Append `-2`, `-3`, ... to the repaired value until it is unique.
+    Suffix,
+    /// Fail the collection.
+    Error,
+}
+
+impl DedupAction {
+    /// All supported actions.
+    pub const ALL: [DedupAction; 3] = [DedupAction::Skip, DedupAction::Suffix, DedupAction::Error];
+
+    /// Human-readable name, as accepted by [`FromStr`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Skip => "skip",
+            Self::Suffix => "suffix",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for DedupAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DedupAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|action| action.name()).collect();
+                format!(
+                    "unknown dedup action {s:?} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Whether a repaired value is unique, as claimed by [`RepairedValues::claim`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Claim {
+    Unique,
+    /// The value collides and the document must not be written.
+    Duplicate,
+    /// The value collided and was replaced by this unique value.
+    Suffixed(String),
+}
+
+/// Repaired values of a field, shared by concurrently repaired collections
+/// to detect values that collide after the repair (e.g. of a unique index).
+#[derive(Debug)]
+pub struct RepairedValues {
+    field: String,
+    action: DedupAction,
+    /// Documents by collection and repaired value.
+    seen: Mutex<HashMap<(String, String), String>>,
+}
+
+impl RepairedValues {
+    /// Track the repaired values of the dot-notation `field`.
+    #[must_use]
+    pub fn new(field: impl Into<String>, action: DedupAction) -> Self {
+        Self {
+            field: field.into(),
+            action,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Dot-notation path of the tracked field.
+    #[must_use]
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Record `value` as the repaired value of document `id` of `collection`.
+    pub(crate) fn claim(&self, collection: &str, id: &str, value: &str) -> crate::Result<Claim> {
+        let mut seen = self
+            .seen
+            .lock()
+            .map_err(|_| RepairError::Other("repaired values are poisoned".to_string()))?;
+        let key = (collection.to_string(), value.to_string());
+        let other_id = match seen.get(&key) {
+            None => {
+                seen.insert(key, id.to_string());
+                return Ok(Claim::Unique);
+            }
+            // a document is processed again when its version changed
+            Some(other_id) if other_id == id => return Ok(Claim::Unique),
+            Some(other_id) => other_id.clone(),
+        };
+        eprintln!(
+            "collection = {: <20} id = {: <30} ERROR repaired {} {value:?} collides with document {other_id}",
+            collection, id, self.field
+        );
+        match self.action {
+            DedupAction::Skip => Ok(Claim::Duplicate),
+            DedupAction::Error => Err(RepairError::DuplicateValue {
+                field: self.field.clone(),
+                value: value.to_string(),
+                id: id.to_string(),
+                other_id,
+            }),
+            DedupAction::Suffix => {
+                let suffixed = (2..)
+                    .map(|n| format!("{value}-{n}"))
+                    .find(|suffixed| {
+                        !seen.contains_key(&(collection.to_string(), suffixed.clone()))
+                    })
+                    .expect("suffixes are unbounded");
+                seen.insert((collection.to_string(), suffixed.clone()), id.to_string());
+                Ok(Claim::Suffixed(suffixed))
+            }
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Encoding used to reinterpret the raw bytes of a string that is not valid UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Encoding {
+    /// Widen every byte to a UTF-16 code unit.
+    ///
+    /// This recovers strings that a legacy client wrote as single-byte Latin-1.
+    #[default]
+    Utf16,
+    /// Decode as UTF-8 and replace invalid sequences with `U+FFFD`.
+    Utf8Lossy,
+    /// Map every byte with the table of
+    /// [`RepairOptions::custom_encoding`](crate::RepairOptions::custom_encoding).
+    ///
+    /// Without a table, bytes are widened like [`Encoding::Utf16`].
+    Custom,
+    /// Repair string values with the
+    /// [`RepairOptions::transform_script`](crate::RepairOptions::transform_script).
+    ///
+    /// Field names, binary values and values without a script are widened like [`Encoding::Utf16`].
+    Script,
+}
+
+impl Encoding {
+    /// All supported encodings.
+    pub const ALL: [Encoding; 4] = [
+        Encoding::Utf16,
+        Encoding::Utf8Lossy,
+        Encoding::Custom,
+        Encoding::Script,
+    ];
+
+    /// Human-readable name, as accepted by [`FromStr`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Utf16 => "utf16",
+            Self::Utf8Lossy => "utf8-lossy",
+            Self::Custom => "custom",
+            Self::Script => "script",
+        }
+    }
+
+    /// Decode `raw` into a valid UTF-8 string.
+    #[must_use]
+    pub fn decode(self, raw: &[u8]) -> String {
+        match self {
+            Self::Utf16 | Self::Custom | Self::Script => {
+                String::from_utf16_lossy(&raw.iter().map(|v| u16::from(*v)).collect::<Vec<_>>())
+            }
+            Self::Utf8Lossy => String::from_utf8_lossy(raw).to_string(),
+        }
+    }
+
+    /// Decode `raw` with the encoding producing the fewest `U+FFFD` replacement
+    /// characters, preferring `self` on ties.
+    #[must_use]
+    pub fn decode_best(self, raw: &[u8]) -> (Encoding, String) {
+        let mut best = (self, self.decode(raw));
+        let mut best_count = best.1.matches(char::REPLACEMENT_CHARACTER).count();
+        for encoding in Self::ALL.into_iter().filter(|encoding| {
+            *encoding != self && !matches!(encoding, Self::Custom | Self::Script)
+        }) {
+            let decoded = encoding.decode(raw);
+            let count = decoded.matches(char::REPLACEMENT_CHARACTER).count();
+            if count < best_count {
+                best = (encoding, decoded);
+                best_count = count;
+            }
+        }
+        best
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|encoding| encoding.name()).collect();
+                format!(
+                    "unknown encoding {s:?} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Single-byte encoding loaded from a table, for [`Encoding::Custom`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomEncoding {
+    chars: [char; 256],
+}
+
+impl CustomEncoding {
+    /// Load the table from a CSV file with a `byte_value,unicode_codepoint` row
+    /// (e.g. `233,00E9`) for each of the 256 byte values.
+    ///
+    /// Byte values are decimal and code points hexadecimal, optionally prefixed
+    /// with `U+` or `0x`. A header row is skipped.
+    pub fn load(path: impl AsRef<std::path::Path>) -> crate::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse the CSV table described in [`CustomEncoding::load`].
+    ///
+    /// ```
+    /// use mongo_repair_utf8::CustomEncoding;
+    ///
+    /// let mut csv = String::from("byte_value,unicode_codepoint\n");
+    /// for byte in 0..=255u32 {
+    ///     // EBCDIC-like: swap the upper and lower half
+    ///     csv.push_str(&format!("{byte},{:04X}\n", byte ^ 0x80));
+    /// }
+    /// let encoding = CustomEncoding::parse(&csv).unwrap();
+    /// assert_eq!(encoding.decode(b"\x69"), "\u{e9}");
+    ///
+    /// assert!(CustomEncoding::parse("0,0041\n").is_err());
+    /// ```
+    pub fn parse(csv: &str) -> crate::Result<Self> {
+        let invalid = |line: usize, reason: String| {
+            crate::RepairError::Other(format!("custom encoding line {line}: {reason}"))
+        };
+        let mut chars: [Option<char>; 256] = [None; 256];
+        let mut entries = 0;
+        for (idx, line) in csv.lines().enumerate() {
+            let line_number = idx + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((byte, codepoint)) = line.split_once(',') else {
+                return Err(invalid(
+                    line_number,
+                    format!("expected two columns, got {line:?}"),
+                ));
+            };
+            let (byte, codepoint) = (byte.trim(), codepoint.trim());
+            let Ok(byte) = byte.parse::<u8>() else {
+                if idx == 0 {
+                    // header
+                    continue;
+                }
+                return Err(invalid(line_number, format!("invalid byte value {byte:?}")));
+            };
+            let digits = codepoint
+                .strip_prefix("U+")
+                .or_else(|| codepoint.strip_prefix("0x"))
+                .unwrap_or(codepoint);
+            let c = u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| invalid(line_number, format!("invalid code point {codepoint:?}")))?;
+            if chars[usize::from(byte)].replace(c).is_some() {
+                return Err(invalid(line_number, format!("duplicate byte value {byte}")));
+            }
+            entries += 1;
+        }
+        let mut table = ['\0'; 256];
+        for (byte, c) in chars.into_iter().enumerate() {
+            table[byte] = c.ok_or_else(|| {
+                crate::RepairError::Other(format!(
+                    "custom encoding has {entries} entries instead of 256, missing byte value {byte}"
+                ))
+            })?;
+        }
+        Ok(Self { chars: table })
+    }
+
+    /// Map every byte of `raw` to its character.
+    #[must_use]
+    pub fn decode(&self, raw: &[u8]) -> String {
+        raw.iter()
+            .map(|byte| self.chars[usize::from(*byte)])
+            .collect()
+    }
+}
+
+/// Decode the code points of `value` as bytes, if they are all below U+0100
+/// and form UTF-8 that differs from `value`.
+///
+/// This reverses UTF-8 that was decoded as Latin-1, as the [`Encoding::Utf16`]
+/// repair does with multi-byte sequences nested in an already broken value.
+pub(crate) fn reinterpret_latin1_as_utf8(value: &str) -> Option<String> {
+    if value.is_ascii() {
+        return None;
+    }
+    let bytes = value
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Describe the first overlong encoding or encoded UTF-16 surrogate (as written
+/// by Java's modified UTF-8 and CESU-8) in `bytes`, which a repair decodes
+/// as unrelated characters.
+pub(crate) fn strict_utf8_violation(bytes: &[u8]) -> Option<String> {
+    bytes
+        .windows(2)
+        .enumerate()
+        .find_map(|(idx, pair)| match pair {
+            [0xC0 | 0xC1, 0x80..=0xBF] | [0xE0, 0x80..=0x9F] | [0xF0, 0x80..=0x8F] => {
+                Some(format!("overlong encoding at byte {idx}"))
+            }
+            [0xED, 0xA0..=0xBF] => Some(format!("encoded surrogate at byte {idx}")),
+            _ => None,
+        })
+}
+
+/// Shannon entropy of `bytes` in bits per byte, from 0 (a single repeated byte)
+/// to 8 (every byte value equally frequent).
+///
+/// ```
+/// use mongo_repair_utf8::byte_entropy;
+///
+/// assert_eq!(byte_entropy(b"aaaa"), 0.0);
+/// assert_eq!(byte_entropy(b"abab"), 1.0);
+/// let all: Vec<u8> = (0..=255).collect();
+/// assert_eq!(byte_entropy(&all), 8.0);
+/// ```
+#[must_use]
+pub fn byte_entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in bytes {
+        counts[usize::from(*byte)] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum::<f64>()
+        .clamp(0.0, 8.0)
+}
+
+/// Likely encoding a repaired `value` was originally written in, judged by the
+/// Unicode blocks of its non-ASCII characters.
+///
+/// ```
+/// use mongo_repair_utf8::probable_source_encoding;
+///
+/// assert_eq!(probable_source_encoding("café"), "latin-1/cp1252");
+/// assert_eq!(probable_source_encoding("привет"), "cp1251/koi8-r");
+/// ```
+#[must_use]
+pub fn probable_source_encoding(value: &str) -> &'static str {
+    const NAMES: [&str; 9] = [
+        "latin-1/cp1252",
+        "cp1250/latin-2",
+        "cp1253/iso-8859-7",
+        "cp1251/koi8-r",
+        "cp1255/iso-8859-8",
+        "cp1256/iso-8859-6",
+        "tis-620",
+        "gbk/big5/shift-jis",
+        "euc-kr",
+    ];
+    let mut counts = [0usize; NAMES.len()];
+    for c in value.chars().filter(|c| !c.is_ascii()) {
+        let block = match u32::from(c) {
+            0x0080..=0x00FF => 0,
+            0x0100..=0x024F => 1,
+            0x0370..=0x03FF => 2,
+            0x0400..=0x052F => 3,
+            0x0590..=0x05FF => 4,
+            0x0600..=0x06FF => 5,
+            0x0E00..=0x0E7F => 6,
+            0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xFF00..=0xFFEF => 7,
+            0x1100..=0x11FF | 0xAC00..=0xD7AF => 8,
+            _ => continue,
+        };
+        counts[block] += 1;
+    }
+    match counts
+        .iter()
+        .enumerate()
+        .max_by_key(|(idx, count)| (**count, usize::MAX - idx))
+    {
+        Some((idx, count)) if *count > 0 => NAMES[idx],
+        _ if value.is_ascii() => "ascii",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,66 @@
+use mongodb::bson;
+
+/// Error of repairing documents.
+#[derive(Debug, thiserror::Error)]
+pub enum RepairError {
+    /// A document or value is not valid BSON.
+    #[error("invalid BSON: {0}")]
+    BsonParse(#[from] bson::raw::Error),
+    /// A field of a raw document is missing or of another type.
+    #[error(transparent)]
+    BsonAccess(#[from] bson::raw::ValueAccessError),
+    /// A field of a document is missing or of another type.
+    #[error(transparent)]
+    DocumentAccess(#[from] bson::document::ValueAccessError),
+    #[error(transparent)]
+    BsonSerialize(#[from] bson::ser::Error),
+    #[error(transparent)]
+    BsonDeserialize(#[from] bson::de::Error),
+    #[error(transparent)]
+    ObjectId(#[from] bson::oid::Error),
+    #[error(transparent)]
+    ExtendedJson(#[from] bson::extjson::de::Error),
+    #[error(transparent)]
+    MongoDb(#[from] mongodb::error::Error),
+    /// The repaired value of `field` could not be encoded.
+    #[error("cannot repair {field}: {reason}")]
+    EncodingFailed { field: String, reason: String },
+    /// A repaired document exceeds [`RepairOptions::max_document_size`](crate::RepairOptions::max_document_size).
+    #[error("repaired document {id} is {size} bytes, exceeding the size limit")]
+    DocumentTooLarge { id: String, size: usize },
+    /// The repaired value of `field` collides with the one of another document,
+    /// for [`RepairOptions::deduplicate_repaired`](crate::RepairOptions::deduplicate_repaired).
+    #[error("repaired {field} {value:?} of document {id} collides with document {other_id}")]
+    DuplicateValue {
+        field: String,
+        value: String,
+        id: String,
+        other_id: String,
+    },
+    /// A repaired value violates the length constraints of its field, for
+    /// [`ConstraintAction::Error`](crate::ConstraintAction::Error).
+    #[error("cannot repair {field}: {reason}")]
+    ConstraintViolation { field: String, reason: String },
+    /// The [`RepairOptions::version_field`](crate::RepairOptions::version_field)
+    /// of a document is not a number or cannot be incremented.
+    #[error("invalid version field {field}: {reason}")]
+    InvalidVersion { field: String, reason: String },
+    /// A change could not be confirmed, e.g. because the terminal was closed.
+    #[error("confirmation was aborted")]
+    ConfirmationAborted,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    IntConversion(#[from] std::num::TryFromIntError),
+    /// Any other failure, described by the message.
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = RepairError> = std::result::Result<T, E>;
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use crate::{RepairDiff, RepairMetrics};
+
+/// Event of a repair, written to a [`ReportStream`] as a line of JSON.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum RepairEvent<'a> {
+    /// Documents of a collection processed so far.
+    Progress {
+        collection: &'a str,
+        processed: u64,
+        total: u64,
+    },
+    DocumentChanged {
+        #[serde(flatten)]
+        changes: &'a RepairDiff,
+    },
+    CollectionFinished {
+        collection: &'a str,
+        metrics: &'a RepairMetrics,
+    },
+}
+
+/// Live JSON Lines report of repair events, e.g. written to a named pipe (FIFO)
+/// read by a monitoring process.
+///
+/// Every event is flushed immediately. Once writing fails (e.g. because the
+/// reader went away), the error is printed and further events are dropped.
+#[derive(Debug)]
+pub struct ReportStream {
+    writer: tokio::sync::Mutex<tokio::fs::File>,
+    /// Handle of the file, synced to disk by [`ReportStream::sync`].
+    sync_handle: std::fs::File,
+    failed: AtomicBool,
+}
+
+impl ReportStream {
+    /// Open the file or FIFO at `path` for writing.
+    ///
+    /// Opening a FIFO waits until a reader opened it.
+    pub async fn open(path: impl AsRef<std::path::Path>) -> crate::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await?;
+        Ok(Self {
+            sync_handle: file.try_clone().await?.into_std().await,
+            writer: tokio::sync::Mutex::new(file),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Sync the written events to disk, unless writing to a FIFO.
+    ///
+    /// Events are flushed as they are written, so this only blocks on the disk.
+    pub fn sync(&self) -> crate::Result<()> {
+        if self.sync_handle.metadata()?.is_file() {
+            self.sync_handle.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn send(&self, event: &RepairEvent<'_>) {
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = self.write(event).await {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "WARNING failed to write to the report stream ({err}), dropping further events"
+                );
+            }
+        }
+    }
+
+    async fn write(&self, event: &RepairEvent<'_>) -> crate::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&line).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::Write;
+use std::sync::Mutex;
+
+use mongodb::bson;
+
+use crate::RepairError;
+
+/// File format of a [`DocumentExport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Concatenated BSON documents, as written by `mongodump`.
+    #[default]
+    Bson,
+    /// One relaxed extended JSON document per line, as written by `mongoexport`.
+    Json,
+}
+
+/// Writes original documents to a file, shared by concurrently repaired collections.
+pub struct DocumentExport {
+    format: ExportFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+    /// The file written to, synced to disk by [`DocumentExport::sync`].
+    file: Option<std::fs::File>,
+}
+
+impl std::fmt::Debug for DocumentExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DocumentExport")
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DocumentExport {
+    #[must_use]
+    pub fn new(writer: impl Write + Send + 'static, format: ExportFormat) -> Self {
+        Self {
+            format,
+            writer: Mutex::new(Box::new(writer)),
+            file: None,
+        }
+    }
+
+    /// Sync `file`, a handle to the file written to, in [`DocumentExport::sync`].
+    #[must_use]
+    pub fn sync_file(mut self, file: std::fs::File) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Create (or truncate) the file at `path`.
+    pub fn create(path: &std::path::Path, format: ExportFormat) -> crate::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        let handle = file.try_clone()?;
+        Ok(Self::new(std::io::BufWriter::new(file), format).sync_file(handle))
+    }
+
+    /// Append `doc` to the export.
+    ///
+    /// Documents that are not valid UTF-8 are written byte for byte as BSON,
+    /// and with invalid sequences replaced as JSON.
+    pub fn write(&self, doc: &bson::RawDocument) -> crate::Result<()> {
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| RepairError::Other("export writer is poisoned".to_string()))?;
+        match self.format {
+            ExportFormat::Bson => writer.write_all(doc.as_bytes())?,
+            ExportFormat::Json => {
+                let doc = bson::Document::from_reader_utf8_lossy(doc.as_bytes())?;
+                let json = bson::Bson::Document(doc).into_relaxed_extjson();
+                serde_json::to_writer(&mut *writer, &json)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush buffered documents to the file.
+    pub fn flush(&self) -> crate::Result<()> {
+        self.writer
+            .lock()
+            .map_err(|_| RepairError::Other("export writer is poisoned".to_string()))?
+            .flush()?;
+        Ok(())
+    }
+
+    /// Flush buffered documents and sync the file to disk.
+    pub fn sync(&self) -> crate::Result<()> {
+        self.flush()?;
+        if let Some(file) = &self.file {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Document replacements counted per collection, to fail every Nth of them
+/// for [`RepairOptions::simulate_write_failure`].
+///
+/// Failed writes are not retried, the document is counted as failed.
+///
+/// [`RepairOptions::simulate_write_failure`]: crate::RepairOptions::simulate_write_failure
+#[derive(Debug)]
+pub struct SimulatedWriteFailure {
+    every: u64,
+    /// Replacements by collection.
+    writes: Mutex<HashMap<String, u64>>,
+}
+
+impl SimulatedWriteFailure {
+    /// Fail every `every`th replacement of each collection.
+    #[must_use]
+    pub fn new(every: u64) -> Self {
+        Self {
+            every,
+            writes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a replacement of `collection` and fail if it is an Nth one.
+    pub(crate) fn write(&self, collection: &str) -> mongodb::error::Result<()> {
+        let mut writes = self
+            .writes
+            .lock()
+            .map_err(|_| std::io::Error::other("simulated write failures are poisoned"))?;
+        let write = writes.entry(collection.to_string()).or_default();
+        *write += 1;
+        if write.is_multiple_of(self.every) {
+            // an I/O error, as custom errors are displayed without their message
+            return Err(std::io::Error::other(format!(
+                "simulated failure of write {write} of collection {collection}"
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_nth_write_of_a_collection_fails() {
+        let failure = SimulatedWriteFailure::new(3);
+        let failed = |collection| {
+            (0..6)
+                .map(|_| failure.write(collection).is_err())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(failed("users"), [false, false, true, false, false, true]);
+        // other collections count their own writes
+        assert!(failure.write("orders").is_ok());
+        assert!(failure.write("orders").is_ok());
+        assert!(failure.write("orders").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use crate::{AuditFile, DocumentExport, RepairError, RepairOptions, ReportStream};
+
+/// Output files of a repair run, flushed and synced to disk when the run ends.
+///
+/// Call [`RepairRunGuard::shutdown`] when the run finished or was interrupted.
+/// If the guard is dropped instead (e.g. on a panic), the files are synced
+/// on a best-effort basis and errors are only printed.
+#[derive(Debug)]
+pub struct RepairRunGuard {
+    exports: Vec<Arc<DocumentExport>>,
+    audit_file: Option<Arc<AuditFile>>,
+    report_stream: Option<Arc<ReportStream>>,
+    finished: bool,
+}
+
+impl RepairRunGuard {
+    /// Guard the exports of `options`
+    /// ([`RepairOptions::export_broken`] and [`RepairOptions::output_bson`]),
+    /// its [`RepairOptions::audit_file`] and its [`RepairOptions::report_stream`].
+    #[must_use]
+    pub fn new(options: &RepairOptions) -> Self {
+        Self {
+            exports: [&options.export_broken, &options.output_bson]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            audit_file: options.audit_file.clone(),
+            report_stream: options.report_stream.clone(),
+            finished: false,
+        }
+    }
+
+    /// Flush and sync all output files, blocking until they are on disk.
+    fn sync(
+        exports: &[Arc<DocumentExport>],
+        audit_file: Option<&AuditFile>,
+        report_stream: Option<&ReportStream>,
+    ) -> crate::Result<()> {
+        exports.iter().try_for_each(|export| export.sync())?;
+        if let Some(audit_file) = audit_file {
+            audit_file.sync()?;
+        }
+        if let Some(report_stream) = report_stream {
+            report_stream.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flush and sync all output files, without blocking the runtime.
+    pub async fn shutdown(mut self) -> crate::Result<()> {
+        self.finished = true;
+        let exports = std::mem::take(&mut self.exports);
+        let audit_file = self.audit_file.take();
+        let report_stream = self.report_stream.take();
+        tokio::task::spawn_blocking(move || {
+            Self::sync(&exports, audit_file.as_deref(), report_stream.as_deref())
+        })
+        .await
+        .map_err(|err| RepairError::Other(format!("failed to sync outputs: {err}")))?
+    }
+}
+
+impl Drop for RepairRunGuard {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        for export in &self.exports {
+            if let Err(err) = export.sync() {
+                eprintln!("failed to sync output: {err}");
+            }
+        }
+        if let Some(Err(err)) = self.audit_file.as_ref().map(|audit_file| audit_file.sync()) {
+            eprintln!("failed to sync the audit file: {err}");
+        }
+        if let Some(Err(err)) = self.report_stream.as_ref().map(|stream| stream.sync()) {
+            eprintln!("failed to sync the report stream: {err}");
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use tokio::process::Command;
+
+/// Run the shell `command` of [`RepairOptions::pre_repair_hook`](crate::RepairOptions::pre_repair_hook)
+/// or [`RepairOptions::post_repair_hook`](crate::RepairOptions::post_repair_hook)
+/// for `collection` of `database`, passed as `$MONGO_COLLECTION` and `$MONGO_DATABASE`.
+///
+/// Returns whether the command exited successfully.
+pub(crate) async fn run_hook(
+    command: &str,
+    database: &str,
+    collection: &str,
+) -> crate::Result<bool> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("MONGO_DATABASE", database)
+        .env("MONGO_COLLECTION", collection)
+        .status()
+        .await?;
+    if !status.success() {
+        eprintln!(
+            "collection = {: <20} WARNING hook {command:?} failed ({status})",
+            collection
+        );
+    }
+    Ok(status.success())
+}
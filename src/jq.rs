@@ -0,0 +1,110 @@
+use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+use mongodb::bson;
+
+use crate::RepairError;
+
+/// Selects documents with a jq expression, for [`RepairOptions::jq_filter`](crate::RepairOptions::jq_filter).
+///
+/// Documents are converted to relaxed extended JSON (with invalid UTF-8 replaced)
+/// and evaluated with [jaq](https://github.com/01mf02/jaq), including its standard library.
+/// A document is selected if the expression yields a value other than `false`
+/// or `null`, so both `.status == "active"` and `select(.status == "active")` work.
+/// Documents for which the expression fails are not selected.
+///
+/// ```
+/// use mongo_repair_utf8::JqFilter;
+///
+/// assert!(JqFilter::new(r#"select(.status == "active")"#).is_ok());
+/// assert!(JqFilter::new("select(.status ==").is_err());
+/// ```
+#[derive(Debug)]
+pub struct JqFilter {
+    expression: String,
+    filter: jaq_interpret::Filter,
+}
+
+impl JqFilter {
+    /// Compile `expression`.
+    pub fn new(expression: impl Into<String>) -> crate::Result<Self> {
+        let expression = expression.into();
+        let (main, errs) = jaq_parse::parse(&expression, jaq_parse::main());
+        let main = match main {
+            Some(main) if errs.is_empty() => main,
+            _ => {
+                let errs: Vec<_> = errs.iter().map(ToString::to_string).collect();
+                return Err(RepairError::Other(format!(
+                    "invalid jq expression {expression:?}: {}",
+                    errs.join(", ")
+                )));
+            }
+        };
+        let mut defs = ParseCtx::new(Vec::new());
+        defs.insert_natives(jaq_core::core());
+        defs.insert_defs(jaq_std::std());
+        let filter = defs.compile(main);
+        if !defs.errs.is_empty() {
+            let errs: Vec<_> = defs.errs.iter().map(|(err, _)| err.to_string()).collect();
+            return Err(RepairError::Other(format!(
+                "invalid jq expression {expression:?}: {}",
+                errs.join(", ")
+            )));
+        }
+        Ok(Self { expression, filter })
+    }
+
+    #[must_use]
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Whether the expression selects `doc`.
+    pub(crate) fn matches(&self, doc: &bson::RawDocument) -> crate::Result<bool> {
+        let doc = bson::Document::from_reader_utf8_lossy(doc.as_bytes())?;
+        let input = Val::from(bson::Bson::Document(doc).into_relaxed_extjson());
+        let inputs = RcIter::new(core::iter::empty());
+        let mut outputs = self.filter.run((Ctx::new([], &inputs), input));
+        // like `try ([EXPR] | any) catch false`
+        Ok(outputs
+            .try_fold(false, |any, value| {
+                value.map(|value| any || value.as_bool())
+            })
+            .unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expression: &str, doc: bson::Document) -> bool {
+        let doc = bson::RawDocumentBuf::from_document(&doc).unwrap();
+        JqFilter::new(expression).unwrap().matches(&doc).unwrap()
+    }
+
+    #[test]
+    fn selects_truthy_outputs() {
+        let doc = bson::doc! { "status": "active", "count": 3 };
+        assert!(matches(r#".status == "active""#, doc.clone()));
+        assert!(matches(r#"select(.status == "active")"#, doc.clone()));
+        assert!(matches(".count > 2", doc.clone()));
+        assert!(!matches(r#"select(.status == "deleted")"#, doc.clone()));
+        assert!(!matches(".missing", doc));
+    }
+
+    #[test]
+    fn errors_are_not_selected() {
+        let doc = bson::doc! { "status": "active" };
+        assert!(!matches(".status | tonumber", doc.clone()));
+        assert!(!matches(r#".status, error("stop")"#, doc));
+    }
+
+    #[test]
+    fn uses_relaxed_extended_json() {
+        let id = bson::oid::ObjectId::new();
+        let doc = bson::doc! { "_id": id, "status": "active" };
+        assert!(matches(
+            &format!(r#"._id."$oid" == "{}""#, id.to_hex()),
+            doc
+        ));
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+use mongodb::bson;
+
+/// How often the replication lag is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pauses a repair while secondaries lag too far behind the primary,
+/// for [`RepairOptions::replication_lag_tolerance`](crate::RepairOptions::replication_lag_tolerance).
+///
+/// Processing pauses once the lag of any secondary exceeds the tolerance
+/// and resumes when it dropped below half of it.
+pub(crate) struct LagThrottle {
+    client: mongodb::Client,
+    tolerance: Duration,
+    last_check: Option<Instant>,
+}
+
+impl LagThrottle {
+    pub(crate) fn new(client: mongodb::Client, tolerance: Duration) -> Self {
+        Self {
+            client,
+            tolerance,
+            last_check: None,
+        }
+    }
+
+    /// Largest lag of a secondary behind the primary, or `None` without a primary.
+    async fn lag(&self) -> crate::Result<Option<Duration>> {
+        let status = self
+            .client
+            .database("admin")
+            .run_command(bson::doc! { "replSetGetStatus": 1 })
+            .await?;
+        let optimes: Vec<(i32, bson::DateTime)> = status
+            .get_array("members")?
+            .iter()
+            .filter_map(bson::Bson::as_document)
+            .filter_map(|member| {
+                Some((
+                    member.get_i32("state").ok()?,
+                    *member.get_datetime("optimeDate").ok()?,
+                ))
+            })
+            .collect();
+        // member states: 1 is PRIMARY, 2 is SECONDARY
+        let Some(primary) = optimes.iter().find(|(state, _)| *state == 1) else {
+            return Ok(None);
+        };
+        let lag = optimes
+            .iter()
+            .filter(|(state, _)| *state == 2)
+            .map(|(_, optime)| primary.1.timestamp_millis() - optime.timestamp_millis())
+            .max()
+            .unwrap_or(0);
+        Ok(Some(Duration::from_millis(lag.max(0).unsigned_abs())))
+    }
+
+    /// Wait until the replication lag is tolerable, checking at most once per second.
+    pub(crate) async fn wait(&mut self, collection: &str) -> crate::Result<()> {
+        if self
+            .last_check
+            .is_some_and(|last_check| last_check.elapsed() < CHECK_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_check = Some(Instant::now());
+        let Some(lag) = self.lag().await? else {
+            return Ok(());
+        };
+        if lag <= self.tolerance {
+            return Ok(());
+        }
+        eprintln!(
+            "collection = {: <20} PAUSED (replication lag of {lag:.2?} exceeds {:.2?})",
+            collection, self.tolerance
+        );
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            match self.lag().await? {
+                Some(lag) if lag >= self.tolerance / 2 => {}
+                lag => {
+                    eprintln!(
+                        "collection = {: <20} RESUMED (replication lag of {:.2?})",
+                        collection,
+                        lag.unwrap_or_default()
+                    );
+                    break;
+                }
+            }
+        }
+        self.last_check = Some(Instant::now());
+        Ok(())
+    }
+}
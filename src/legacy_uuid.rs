@@ -0,0 +1,116 @@
+use std::fmt;
+use std::str::FromStr;
+
+use mongodb::bson;
+use mongodb::bson::uuid::UuidRepresentation;
+
+/// Byte order of legacy UUIDs (binary subtype 3), which depends on the driver that wrote them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LegacyUuidStyle {
+    /// Both 8 byte halves reversed, as written by the legacy Java driver.
+    Java,
+    /// The first three groups reversed, as written by the legacy C# driver.
+    CSharp,
+    /// Standard byte order, as written by the legacy Python driver.
+    Python,
+}
+
+impl LegacyUuidStyle {
+    /// All supported styles.
+    pub const ALL: [LegacyUuidStyle; 3] = [
+        LegacyUuidStyle::Java,
+        LegacyUuidStyle::CSharp,
+        LegacyUuidStyle::Python,
+    ];
+
+    /// Human-readable name, as accepted by [`FromStr`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Java => "java",
+            Self::CSharp => "csharp",
+            Self::Python => "python",
+        }
+    }
+
+    fn representation(self) -> UuidRepresentation {
+        match self {
+            Self::Java => UuidRepresentation::JavaLegacy,
+            Self::CSharp => UuidRepresentation::CSharpLegacy,
+            Self::Python => UuidRepresentation::PythonLegacy,
+        }
+    }
+}
+
+impl fmt::Display for LegacyUuidStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LegacyUuidStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|style| style.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|style| style.name()).collect();
+                format!(
+                    "unknown legacy UUID style {s:?} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The standard UUID (binary subtype 4) of a legacy UUID `binary` written in `style`,
+/// or `None` if `binary` is not a legacy UUID.
+///
+/// The UUID `00112233-4455-6677-8899-aabbccddeeff` in the byte order of each style:
+///
+/// ```
+/// use mongo_repair_utf8::{normalize_legacy_uuid, LegacyUuidStyle};
+/// use mongodb::bson::{spec::BinarySubtype, Binary};
+///
+/// let legacy = |bytes: [u8; 16]| Binary {
+///     subtype: BinarySubtype::UuidOld,
+///     bytes: bytes.to_vec(),
+/// };
+/// let java = legacy([
+///     0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00,
+///     0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa, 0x99, 0x88,
+/// ]);
+/// let csharp = legacy([
+///     0x33, 0x22, 0x11, 0x00, 0x55, 0x44, 0x77, 0x66,
+///     0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+/// ]);
+/// let python = legacy([
+///     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+///     0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+/// ]);
+/// for (binary, style) in [
+///     (java, LegacyUuidStyle::Java),
+///     (csharp, LegacyUuidStyle::CSharp),
+///     (python, LegacyUuidStyle::Python),
+/// ] {
+///     let uuid = normalize_legacy_uuid(&binary, style).unwrap();
+///     assert_eq!(uuid.to_string(), "00112233-4455-6677-8899-aabbccddeeff");
+/// }
+///
+/// let standard = Binary {
+///     subtype: BinarySubtype::Uuid,
+///     bytes: vec![0; 16],
+/// };
+/// assert!(normalize_legacy_uuid(&standard, LegacyUuidStyle::Java).is_none());
+/// ```
+#[must_use]
+pub fn normalize_legacy_uuid(binary: &bson::Binary, style: LegacyUuidStyle) -> Option<bson::Uuid> {
+    if binary.subtype != bson::spec::BinarySubtype::UuidOld {
+        return None;
+    }
+    binary
+        .to_uuid_with_representation(style.representation())
+        .ok()
+}
@@ -0,0 +1,84 @@
+//! Repair string values in MongoDB documents that are not valid UTF-8.
+//!
+//! The `mongo-repair-utf8` binary is a thin CLI over this library.
+//! Programmatic users build [`RepairOptions`] directly and call
+//! [`fix_collection`] or [`fix_document`]:
+//!
+//! ```no_run
+//! use mongo_repair_utf8::{fix_collection, Encoding, RepairOptions};
+//! use mongodb::{bson, Client};
+//!
+//! # async fn run() -> color_eyre::eyre::Result<()> {
+//! let client = Client::with_uri_str("mongodb://localhost:27017").await?;
+//! let db = client.database("app");
+//! let options = RepairOptions::default()
+//!     .encoding(Encoding::Utf16)
+//!     .dry_run(true)
+//!     .build();
+//! for name in db.list_collection_names().await? {
+//!     fix_collection(db.collection::<bson::RawDocumentBuf>(&name), &options).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod audit;
+mod blocklist;
+mod changes;
+mod confirm;
+mod constraint;
+mod dedup;
+mod encoding;
+mod error;
+mod events;
+mod export;
+#[cfg(debug_assertions)]
+mod failure;
+mod guard;
+mod hook;
+mod jq;
+mod lag;
+mod legacy_uuid;
+mod oplog;
+mod options;
+mod order;
+mod plan;
+mod progress;
+mod repair;
+mod report;
+mod scan;
+mod script;
+mod session;
+mod split;
+mod stats;
+pub mod testing;
+mod timing;
+mod verify;
+
+pub use audit::AuditFile;
+pub use blocklist::FieldBlocklist;
+pub use changes::{
+    apply_changes, read_audit_log, replay_changes, ChangeKind, FieldChange, RepairDiff,
+};
+pub use confirm::{AlwaysConfirm, ConfirmationStrategy, InteractiveConfirm, NeverConfirm};
+pub use constraint::ConstraintAction;
+pub use dedup::{DedupAction, RepairedValues};
+pub use encoding::{byte_entropy, probable_source_encoding, CustomEncoding, Encoding};
+pub use error::{RepairError, Result};
+pub use events::ReportStream;
+pub use export::{DocumentExport, ExportFormat};
+#[cfg(debug_assertions)]
+pub use failure::SimulatedWriteFailure;
+pub use guard::RepairRunGuard;
+pub use jq::JqFilter;
+pub use legacy_uuid::{normalize_legacy_uuid, LegacyUuidStyle};
+pub use oplog::fix_oplog;
+pub use options::{RepairOptions, MAX_DOCUMENT_SIZE};
+pub use order::CollectionOrder;
+pub use plan::BatchRepairPlan;
+pub use repair::{explain_collection, fix_collection, fix_document};
+pub use report::{CollectionReport, RepairMetrics, RunReport};
+pub use script::TransformScript;
+pub use session::RepairSession;
+pub use split::SplitStrategy;
+pub use stats::FieldStats;
@@ -1,22 +1,274 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use color_eyre::eyre;
-use dialoguer::Confirm;
-use futures::stream::{self, StreamExt};
-use futures::TryStreamExt;
+use flate2::write::GzEncoder;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mongo_repair_utf8::{
+    apply_changes, explain_collection, fix_collection, fix_oplog, read_audit_log, replay_changes,
+    testing::make_corrupt_doc, AuditFile, BatchRepairPlan, CollectionOrder, ConfirmationStrategy,
+    ConstraintAction, CustomEncoding, DedupAction, DocumentExport, Encoding, ExportFormat,
+    FieldBlocklist, FieldStats, InteractiveConfirm, JqFilter, LegacyUuidStyle, NeverConfirm,
+    RepairDiff, RepairOptions, RepairRunGuard, RepairSession, ReportStream, RunReport,
+    SplitStrategy, TransformScript,
+};
+use mongodb::options::{
+    AuthMechanism, ClientOptions, Compressor, Credential, ReadConcern, ServerAddress,
+};
 use mongodb::{bson, Client};
-use pretty_assertions::Comparison;
+use rand::Rng;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Parser)]
+mod secrets;
+
+use secrets::{read_aws_secret, read_gcp_secret, read_vault_secret};
+
+#[derive(Debug, Clone, Parser)]
 #[command(author, version, about, long_about = None)]
-#[command(propagate_version = true)]
+#[command(propagate_version = true, subcommand_negates_reqs = true)]
 pub struct Options {
-    #[arg(long = "uri", help = "MongoDB connection URI")]
-    pub connection_uri: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[arg(
+        long = "uri",
+        alias = "source-uri",
+        required_unless_present_any = ["vault_uri", "aws_secret_id", "gcp_secret_name"],
+        help = "MongoDB connection URI (--source-uri when writing to --dest-uri)"
+    )]
+    pub connection_uri: Option<String>,
+    #[arg(
+        long = "dest-uri",
+        conflicts_with_all = ["document_projection", "oplog_replay"],
+        help = "Upsert all documents, repaired where needed, into this MongoDB cluster instead of replacing them"
+    )]
+    pub dest_uri: Option<String>,
+    #[arg(
+        long = "vault-uri",
+        value_name = "VAULT_ADDR",
+        requires = "vault_secret_path",
+        conflicts_with = "connection_uri",
+        help = "Read the connection URI from a HashiCorp Vault secret at this address"
+    )]
+    pub vault_uri: Option<String>,
+    #[arg(
+        long = "vault-secret-path",
+        requires = "vault_uri",
+        help = "Path of the Vault secret holding the connection URI (e.g. secret/data/mongodb)"
+    )]
+    pub vault_secret_path: Option<String>,
+    #[arg(
+        long = "vault-secret-key",
+        default_value = "uri",
+        help = "Key of the connection URI in the Vault secret"
+    )]
+    pub vault_secret_key: String,
+    #[arg(
+        long = "vault-token",
+        help = "Vault token (defaults to the VAULT_TOKEN environment variable)"
+    )]
+    pub vault_token: Option<String>,
+    #[arg(
+        long = "aws-secret-id",
+        conflicts_with_all = ["connection_uri", "vault_uri"],
+        help = "Read the connection URI from this AWS Secrets Manager secret (name or ARN)"
+    )]
+    pub aws_secret_id: Option<String>,
+    #[arg(
+        long = "aws-secret-key",
+        value_name = "JSON_KEY",
+        requires = "aws_secret_id",
+        help = "Key of the connection URI in a JSON-encoded AWS secret (defaults to a plain string secret)"
+    )]
+    pub aws_secret_key: Option<String>,
+    #[arg(
+        long = "gcp-secret-name",
+        value_name = "NAME",
+        conflicts_with_all = ["connection_uri", "vault_uri", "aws_secret_id"],
+        help = "Read the connection URI from this GCP Secret Manager secret (projects/P/secrets/S[/versions/V])"
+    )]
+    pub gcp_secret_name: Option<String>,
+    #[arg(
+        long = "dest-database",
+        requires = "dest_uri",
+        help = "Database to write to on --dest-uri (defaults to --database)"
+    )]
+    pub dest_database: Option<String>,
+    #[arg(
+        long = "dest-collection",
+        requires = "dest_uri",
+        help = "Collection to write to on --dest-uri (defaults to the repaired collection)"
+    )]
+    pub dest_collection: Option<String>,
+    #[arg(
+        long = "auth-mechanism",
+        help = "Authentication mechanism (e.g. SCRAM-SHA-256, GSSAPI, MONGODB-AWS)"
+    )]
+    pub auth_mechanism: Option<AuthMechanism>,
+    #[arg(
+        long = "kerberos-service-name",
+        default_value = "mongodb",
+        help = "Kerberos service name for GSSAPI (requires a ticket cache populated with kinit)"
+    )]
+    pub kerberos_service_name: String,
+    #[arg(
+        long = "aws-access-key-id",
+        requires = "aws_secret_access_key",
+        help = "AWS access key ID for MONGODB-AWS and --aws-secret-id (defaults to the AWS credential chain)"
+    )]
+    pub aws_access_key_id: Option<String>,
+    #[arg(
+        long = "aws-secret-access-key",
+        requires = "aws_access_key_id",
+        help = "AWS secret access key for MONGODB-AWS"
+    )]
+    pub aws_secret_access_key: Option<String>,
+    #[arg(
+        long = "aws-session-token",
+        requires = "aws_access_key_id",
+        help = "AWS session token for MONGODB-AWS"
+    )]
+    pub aws_session_token: Option<String>,
+    #[arg(
+        long = "skip-types-check",
+        help = "Do not check the deployment type (e.g. Atlas Serverless) for unsupported features"
+    )]
+    pub skip_types_check: bool,
+    #[arg(
+        long = "connection-retry",
+        value_name = "ATTEMPTS",
+        default_value_t = 3,
+        help = "Number of attempts to ping the deployment before giving up, e.g. while it is starting up"
+    )]
+    pub connection_retry: u32,
+    #[arg(
+        long = "connection-retry-delay-ms",
+        value_name = "MS",
+        default_value_t = 2000,
+        help = "Delay before the first retry of the ping, doubled after every failed attempt"
+    )]
+    pub connection_retry_delay_ms: u64,
+    #[arg(
+        long = "compress-wire",
+        value_enum,
+        help = "Compress the wire protocol traffic"
+    )]
+    pub compress_wire: Option<WireCompressor>,
+    #[arg(
+        long = "read-concern",
+        value_enum,
+        default_value = "default",
+        help = "Read concern of the cursor; snapshot reads each collection at one point in time in a snapshot session, which requires a replica set or sharded cluster (MongoDB 5.0+) and keeps old document versions in the server's cache"
+    )]
+    pub read_concern: ReadConcernLevel,
     #[arg(long = "database", aliases = ["db"], help = "MongoDB database name")]
     pub database_name: Option<String>,
+    #[arg(
+        long = "all-databases",
+        conflicts_with_all = ["database_name", "dest_uri", "oplog_replay", "explain", "watch_interval"],
+        help = "Repair every database except admin, config and local"
+    )]
+    pub all_databases: bool,
+    #[arg(
+        long = "parallel-databases",
+        value_name = "N",
+        default_value_t = 1,
+        requires = "all_databases",
+        help = "Number of databases repaired concurrently with --all-databases"
+    )]
+    pub parallel_databases: usize,
     #[arg(long = "collection", help = "MongoDB collection names")]
     pub collection_names: Vec<String>,
-    #[arg(long = "confirm", help = "Confirm changes interactively")]
+    #[arg(
+        long = "start-from-collection",
+        value_name = "NAME",
+        help = "Repair collections in alphabetical order, starting with this one"
+    )]
+    pub start_from_collection: Option<String>,
+    #[arg(
+        long = "stop-after-collection",
+        value_name = "NAME",
+        help = "Repair collections in alphabetical order, stopping after this one"
+    )]
+    pub stop_after_collection: Option<String>,
+    #[arg(
+        long = "collection-timeout",
+        value_name = "SECS",
+        help = "Abandon a collection after this many seconds and continue with the next one"
+    )]
+    pub collection_timeout: Option<u64>,
+    #[arg(
+        long = "collection-order",
+        default_value = "natural",
+        help = "Order of the collections (natural, alphabetical, reverse, size-asc, size-desc); size-* sort by the storageSize of collStats"
+    )]
+    pub collection_order: CollectionOrder,
+    #[arg(
+        long = "prioritize-collections",
+        value_name = "NAMES",
+        value_delimiter = ',',
+        help = "Repair these collections first, in this order"
+    )]
+    pub prioritize_collections: Vec<String>,
+    #[arg(
+        long = "pre-repair-hook",
+        value_name = "COMMAND",
+        help = "Shell command run before each collection, with $MONGO_DATABASE and $MONGO_COLLECTION set; the collection is skipped if it fails"
+    )]
+    pub pre_repair_hook: Option<String>,
+    #[arg(
+        long = "post-repair-hook",
+        value_name = "COMMAND",
+        help = "Shell command run after each collection, with $MONGO_DATABASE and $MONGO_COLLECTION set"
+    )]
+    pub post_repair_hook: Option<String>,
+    #[arg(
+        long = "oplog-replay",
+        requires_all = ["database_name", "collection_names"],
+        help = "Repair the inserts and updates of the collections recorded in the oplog and re-apply them"
+    )]
+    pub oplog_replay: bool,
+    #[arg(
+        long = "interactive",
+        conflicts_with_all = ["yes", "no"],
+        help = "Ask before each field is changed"
+    )]
+    pub interactive: bool,
+    #[arg(
+        short = 'y',
+        long = "yes",
+        conflicts_with = "no",
+        help = "Change all repaired fields without asking (default)"
+    )]
+    pub yes: bool,
+    #[arg(
+        long = "no",
+        help = "Change no fields, only print the repairs that would be made"
+    )]
+    pub no: bool,
+    #[arg(
+        long = "default-confirm",
+        value_name = "BOOL",
+        help = "Answer used when an --interactive prompt cannot be read (e.g. on EOF) instead of failing"
+    )]
+    pub default_confirm: Option<bool>,
+    #[arg(
+        long = "batch-plan",
+        conflicts_with_all = ["interactive", "no", "dry_run", "output_bson", "export_broken_only", "dest_uri", "oplog_replay", "explain", "watch_interval", "all_databases"],
+        help = "Compute the changes of all collections in a dry run first, show them, and apply them in bulk after a single confirmation (skipped with --yes)"
+    )]
+    pub batch_plan: bool,
+    #[arg(
+        long = "plan-file",
+        value_name = "FILE",
+        requires = "batch_plan",
+        help = "Write the --batch-plan to this JSON file (as read by apply-changes) instead of printing it"
+    )]
+    pub plan_file: Option<PathBuf>,
+    #[arg(
+        long = "confirm",
+        hide = true,
+        conflicts_with_all = ["interactive", "yes", "no"],
+        help = "Deprecated: use --interactive (true) or --yes (false)"
+    )]
     pub confirm: Option<bool>,
     #[arg(
         long = "dry-run",
@@ -24,184 +276,1286 @@ pub struct Options {
         help = "Run in dry run mode"
     )]
     pub dry_run: bool,
+    #[arg(
+        long = "dry-run-count",
+        conflicts_with = "interactive",
+        help = "Run in dry run mode, only printing the number of documents and fields that would be changed"
+    )]
+    pub dry_run_count: bool,
+    #[arg(
+        long = "dry-run-output-json",
+        requires = "dry_run",
+        help = "Write the proposed changes of a dry run to this JSON file"
+    )]
+    pub dry_run_output_json: Option<PathBuf>,
+    #[arg(
+        long = "export-broken-only",
+        value_name = "FILE",
+        help = "Write the original documents with broken values to this file (BSON, or extended JSON with --output-format json) without repairing them"
+    )]
+    pub export_broken_only: Option<PathBuf>,
+    #[arg(
+        long = "output-bson",
+        value_name = "FILE",
+        conflicts_with_all = ["export_broken_only", "document_projection"],
+        help = "Write all documents, repaired where needed, to this BSON file instead of replacing them"
+    )]
+    pub output_bson: Option<PathBuf>,
+    #[arg(
+        long = "only-changed",
+        help = "Only write documents with repaired values to --output-bson or --dest-uri"
+    )]
+    pub only_changed: bool,
+    #[arg(
+        long = "compress-output",
+        help = "Gzip-compress output files, appending .gz to their names"
+    )]
+    pub compress_output: bool,
+    #[arg(
+        long = "compression-level",
+        default_value = "6",
+        value_parser = clap::value_parser!(u32).range(0..=9),
+        help = "Gzip compression level of --compress-output (0-9)"
+    )]
+    pub compression_level: u32,
+    #[arg(
+        long = "encoding",
+        default_value_t = Encoding::default(),
+        help = "Encoding used to reinterpret invalid UTF-8 strings (utf16, utf8-lossy, custom, script)"
+    )]
+    pub encoding: Encoding,
+    #[arg(
+        long = "custom-encoding-file",
+        value_name = "CSV",
+        conflicts_with = "split_encoding",
+        help = "Table of --encoding custom, with a byte_value,unicode_codepoint (hex) row for each of the 256 bytes"
+    )]
+    pub custom_encoding_file: Option<PathBuf>,
+    #[arg(
+        long = "field-transform-script",
+        value_name = "PATH",
+        conflicts_with = "split_encoding",
+        help = "Rhai script of --encoding script, evaluating to the repaired string of the blob old_bytes at field_path"
+    )]
+    pub field_transform_script: Option<PathBuf>,
+    #[arg(
+        long = "field-transform-timeout-ms",
+        value_name = "MS",
+        default_value_t = 1000,
+        requires = "field_transform_script",
+        help = "Abort the --field-transform-script after this many milliseconds and fail the value"
+    )]
+    pub field_transform_timeout_ms: u64,
+    #[arg(
+        long = "split-encoding",
+        help = "Decode each value with the encoding producing the fewest replacement characters (ties prefer --encoding)"
+    )]
+    pub split_encoding: bool,
+    #[arg(
+        long = "concurrency",
+        visible_alias = "max-parallel-collections-per-database",
+        default_value = "1",
+        help = "Number of collections of a database repaired concurrently"
+    )]
+    pub concurrency: usize,
+    #[arg(
+        long = "parallel-documents",
+        default_value = "1",
+        help = "Number of documents of a collection repaired concurrently"
+    )]
+    pub parallel_documents: usize,
+    #[arg(
+        long = "shard-key",
+        value_name = "FIELD",
+        help = "Shard key field included in replacement filters on sharded collections"
+    )]
+    pub shard_key: Option<String>,
+    #[arg(
+        long = "pipeline",
+        value_name = "JSON",
+        value_parser = parse_pipeline,
+        conflicts_with = "document_projection",
+        help = "Aggregation stages (a JSON array) selecting the documents to repair; they must keep the _id"
+    )]
+    pub pipeline: Option<Pipeline>,
+    #[arg(
+        long = "document-filter-jq",
+        value_name = "EXPR",
+        help = "Only repair documents for which this jq expression is neither false nor null, e.g. 'select(.status == \"active\")'"
+    )]
+    pub document_filter_jq: Option<String>,
+    #[arg(
+        long = "document-projection",
+        value_name = "JSON",
+        value_parser = parse_projection,
+        conflicts_with = "repair_keys",
+        help = "Only read these top-level fields; changed documents are re-read in full before they are replaced"
+    )]
+    pub document_projection: Option<bson::Document>,
+    #[arg(
+        long = "hint",
+        value_name = "INDEX_SPEC",
+        value_parser = parse_document,
+        help = "Index to use for reading the documents, e.g. '{\"createdAt\": 1}'"
+    )]
+    pub hint: Option<bson::Document>,
+    #[arg(
+        long = "randomize-order",
+        help = "Read the documents in random order to avoid hotspots on sharded clusters"
+    )]
+    pub randomize_order: bool,
+    #[arg(
+        long = "two-pass-scan",
+        conflicts_with_all = ["randomize_order", "pipeline", "oplog_replay"],
+        help = "Scan each collection for broken documents first, then read only those in batches of --bulk-find-batch ids to repair them"
+    )]
+    pub two_pass_scan: bool,
+    #[arg(
+        long = "watch-interval",
+        value_name = "SECS",
+        help = "Scan the collections again every SECS seconds, until interrupted"
+    )]
+    pub watch_interval: Option<u64>,
+    #[arg(
+        long = "watch-jitter",
+        value_name = "MS",
+        default_value_t = 0,
+        requires = "watch_interval",
+        help = "Add a random delay of up to MS milliseconds to the --watch-interval, so that instances do not scan in lockstep"
+    )]
+    pub watch_jitter: u64,
+    #[arg(
+        long = "watch-max-idle-ms",
+        value_name = "MS",
+        requires = "watch_interval",
+        help = "Stop watching once no broken documents were found for MS milliseconds"
+    )]
+    pub watch_max_idle_ms: Option<u64>,
+    #[arg(
+        long = "explain",
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "query-planner",
+        conflicts_with = "oplog_replay",
+        help = "Print the query plan of reading each collection instead of repairing it"
+    )]
+    pub explain: Option<ExplainVerbosity>,
+    #[arg(
+        long = "timing-profile",
+        help = "Print p50/p95/p99 latencies of fetching, fixing and writing documents per collection"
+    )]
+    pub timing_profile: bool,
+    #[arg(
+        long = "repair-embedded-json",
+        help = "Also repair the string values of JSON objects and arrays stored in repaired strings"
+    )]
+    pub repair_embedded_json: bool,
+    #[arg(
+        long = "normalize-whitespace",
+        help = "Collapse whitespace (including no-break and em spaces) in repaired strings into single spaces and trim them"
+    )]
+    pub normalize_whitespace: bool,
+    #[arg(
+        long = "graceful-degradation",
+        help = "Keep the lossy UTF-8 decoding of values whose repair has more replacement characters than it"
+    )]
+    pub graceful_degradation: bool,
+    #[arg(
+        long = "validate-utf8-strictly",
+        conflicts_with = "graceful_degradation",
+        help = "Fail documents with overlong encodings or encoded surrogates (e.g. from Java), or whose decoding replaced invalid sequences"
+    )]
+    pub validate_utf8_strictly: bool,
+    #[arg(
+        long = "test-idempotency",
+        help = "Repair every repaired document a second time and log an error if that changes it"
+    )]
+    pub test_idempotency: bool,
+    #[arg(
+        long = "field-blocklist-file",
+        value_name = "FILE",
+        help = "Do not repair the dot-notation field paths listed in this file (one per line, # comments), reloaded when it changes"
+    )]
+    pub field_blocklist_file: Option<PathBuf>,
+    #[arg(
+        long = "report-raw-bytes",
+        help = "Include the hex encoding of the original bytes of repaired values in the output and --dry-run-output-json"
+    )]
+    pub report_raw_bytes: bool,
+    #[arg(
+        long = "max-hex-bytes",
+        default_value = "256",
+        requires = "report_raw_bytes",
+        help = "Maximum number of bytes of a value included by --report-raw-bytes"
+    )]
+    pub max_hex_bytes: usize,
+    #[arg(
+        long = "cursor-max-time-ms",
+        value_name = "MS",
+        help = "Let the server kill the find cursor of a collection after this many milliseconds"
+    )]
+    pub cursor_max_time_ms: Option<u64>,
+    #[arg(
+        long = "version-field",
+        value_name = "FIELD",
+        help = "Only replace documents whose version field is unchanged, and increment it (e.g. __v)"
+    )]
+    pub version_field: Option<String>,
+    #[arg(
+        long = "max-retries",
+        default_value = "3",
+        help = "Number of times a document is repaired again after its --version-field changed"
+    )]
+    pub max_retries: u32,
+    #[arg(
+        long = "replication-lag-tolerance-ms",
+        value_name = "MS",
+        help = "Pause while a secondary lags more than this many milliseconds behind the primary"
+    )]
+    pub replication_lag_tolerance_ms: Option<u64>,
+    #[arg(
+        long = "noop-writes",
+        help = "Prepare replacements but do not send them (for profiling)"
+    )]
+    pub noop_writes: bool,
+    #[cfg(debug_assertions)]
+    #[arg(long = "simulate-write-failure", value_name = "N", hide = true)]
+    pub simulate_write_failure: Option<u64>,
+    #[arg(
+        long = "verify-before-repair",
+        help = "Re-read each document before replacing it and skip it if it changed"
+    )]
+    pub verify_before_repair: bool,
+    #[arg(
+        long = "validate-post-repair",
+        help = "Read replaced documents back and check that they are valid UTF-8"
+    )]
+    pub validate_post_repair: bool,
+    #[arg(
+        long = "bulk-find-batch",
+        value_name = "N",
+        default_value = "100",
+        help = "Number of documents read back per query by --validate-post-repair, or read per query by --two-pass-scan"
+    )]
+    pub bulk_find_batch: usize,
+    #[arg(
+        long = "schema-file",
+        help = "JSON Schema file that repaired documents must satisfy before they are written"
+    )]
+    pub schema_file: Option<PathBuf>,
+    #[arg(
+        long = "collection-validator-check",
+        help = "Fail when a repaired document violates the collection's $jsonSchema validator"
+    )]
+    pub collection_validator_check: bool,
+    #[arg(
+        long = "update-timestamps",
+        value_name = "FIELD",
+        help = "Set this field to the current time on every changed document"
+    )]
+    pub update_timestamps: Option<String>,
+    #[arg(
+        long = "updated-by",
+        help = "Set the updatedBy field to this value on every changed document"
+    )]
+    pub updated_by: Option<String>,
+    #[arg(
+        long = "repair-regex",
+        value_name = "PATTERN",
+        help = "Only repair values whose lossy UTF-8 rendering matches this regex"
+    )]
+    pub repair_regex: Option<regex::Regex>,
+    #[arg(
+        long = "skip-regex",
+        value_name = "PATTERN",
+        help = "Skip documents whose hex _id matches this regex"
+    )]
+    pub skip_regex: Option<regex::Regex>,
+    #[arg(
+        long = "field-type-whitelist",
+        value_name = "TYPES",
+        value_delimiter = ',',
+        value_parser = parse_field_type,
+        help = "BSON types whose values are repaired (string, symbol, javascript) [default: string]"
+    )]
+    pub field_type_whitelist: Vec<bson::spec::ElementType>,
+    #[arg(
+        long = "include-binary-fields",
+        help = "Report generic binary values that contain text in the --encoding"
+    )]
+    pub include_binary_fields: bool,
+    #[arg(
+        long = "normalize-legacy-uuid",
+        value_name = "STYLE",
+        help = "Convert legacy UUIDs (binary subtype 3) written by this driver (java, csharp, python) to standard UUIDs (subtype 4)"
+    )]
+    pub normalize_legacy_uuid: Option<LegacyUuidStyle>,
+    #[arg(
+        long = "fix-binary-fields",
+        requires = "include_binary_fields",
+        help = "Convert binary values that contain text to strings"
+    )]
+    pub fix_binary_fields: bool,
+    #[arg(
+        long = "max-replacement-ratio",
+        value_name = "RATIO",
+        help = "Do not repair values whose repaired length exceeds this multiple of the original length"
+    )]
+    pub max_replacement_ratio: Option<f64>,
+    #[arg(
+        long = "field-entropy-threshold",
+        value_name = "BITS",
+        default_value_t = 8.0,
+        help = "Do not repair values whose raw bytes have a Shannon entropy above this many bits per byte (at most 8), which hints at binary data"
+    )]
+    pub field_entropy_threshold: f64,
+    #[arg(
+        long = "field-ascii-ratio-threshold",
+        value_name = "RATIO",
+        default_value_t = 1.0,
+        help = "Do not repair values of which more than this fraction of raw bytes are ASCII (e.g. 0.9; the default of 1 repairs every value)"
+    )]
+    pub field_ascii_ratio_threshold: f64,
+    #[arg(
+        long = "rename-field",
+        value_name = "OLD=NEW",
+        value_parser = parse_rename,
+        help = "Move a field to a new dot-notation path in repaired documents (repeatable)"
+    )]
+    pub rename_fields: Vec<(String, String)>,
+    #[arg(
+        long = "field-value-min-length",
+        value_name = "PATH=N",
+        value_parser = parse_length_constraint,
+        help = "Minimum length in characters of the repaired values of a dot-notation path (repeatable)"
+    )]
+    pub field_min_lengths: Vec<(String, usize)>,
+    #[arg(
+        long = "field-value-max-length",
+        value_name = "PATH=N",
+        value_parser = parse_length_constraint,
+        help = "Maximum length in characters of the repaired values of a dot-notation path (repeatable)"
+    )]
+    pub field_max_lengths: Vec<(String, usize)>,
+    #[arg(
+        long = "constraint-violation-action",
+        default_value = "skip",
+        help = "What to do with repaired values violating a length constraint (skip, truncate, error)"
+    )]
+    pub constraint_violation_action: ConstraintAction,
+    #[arg(
+        long = "repair-keys",
+        help = "Also repair field names that are not valid UTF-8 (only reported without --rename-repaired-keys)"
+    )]
+    pub repair_keys: bool,
+    #[arg(
+        long = "rename-repaired-keys",
+        requires = "repair_keys",
+        help = "Rename fields to their repaired names, unless a field with that name exists"
+    )]
+    pub rename_repaired_keys: bool,
+    #[arg(
+        long = "max-doc-size-mb",
+        default_value = "16",
+        help = "Maximum size in MiB of a repaired document"
+    )]
+    pub max_doc_size_mb: usize,
+    #[arg(
+        long = "strict-size",
+        help = "Fail instead of skipping repaired documents that exceed --max-doc-size-mb"
+    )]
+    pub strict_size: bool,
+    #[arg(
+        long = "split-large-documents",
+        value_name = "STRATEGY",
+        help = "Shrink repaired documents exceeding --max-doc-size-mb (error, truncate, split-array)"
+    )]
+    pub split_large_documents: Option<SplitStrategy>,
+    #[arg(
+        long = "deduplicate-repaired",
+        value_name = "FIELD",
+        help = "Detect repaired values of this dot-notation field that collide with another document's, e.g. of a unique index"
+    )]
+    pub deduplicate_repaired: Option<String>,
+    #[arg(
+        long = "dedup-action",
+        default_value = "skip",
+        requires = "deduplicate_repaired",
+        help = "What to do with a colliding document (skip, suffix, error)"
+    )]
+    pub dedup_action: DedupAction,
+    #[arg(
+        long = "diff-context-lines",
+        default_value = "3",
+        help = "Number of unchanged lines shown around changes in document diffs"
+    )]
+    pub diff_context_lines: usize,
+    #[arg(
+        long = "abort-on-error",
+        help = "Stop the whole run with a non-zero exit code on the first error"
+    )]
+    pub abort_on_error: bool,
+    #[arg(
+        long = "ignore-fields-with-errors",
+        help = "Copy fields that cannot be parsed verbatim instead of failing the document"
+    )]
+    pub ignore_fields_with_errors: bool,
+    #[arg(long = "verbose", help = "Print additional details of each repair")]
+    pub verbose: bool,
+    #[arg(
+        long = "quiet",
+        help = "Only print documents with repaired values and the final summary"
+    )]
+    pub quiet: bool,
+    #[arg(
+        long = "skip-unchanged",
+        help = "Do not print documents without repaired values (unless --verbose)"
+    )]
+    pub skip_unchanged: bool,
+    #[arg(
+        long = "progress-interval",
+        default_value = "1000",
+        help = "Print a progress line every N documents when stderr is not a terminal (0 disables progress)"
+    )]
+    pub progress_interval: u64,
+    #[arg(
+        long = "max-errors-per-collection",
+        default_value = "0",
+        help = "Stop repairing a collection after N documents failed (0 means no limit)"
+    )]
+    pub max_errors_per_collection: u64,
+    #[arg(
+        long = "min-changed-fields",
+        value_name = "N",
+        default_value_t = 1,
+        help = "Only write back documents with at least N changed fields, skipping the others"
+    )]
+    pub min_changed_fields: usize,
+    #[arg(long = "stats", help = "Print repair statistics at the end of the run")]
+    pub stats: bool,
+    #[arg(
+        long = "char-encoding-report",
+        help = "Print the probable source encoding of the repaired values per collection"
+    )]
+    pub char_encoding_report: bool,
+    #[arg(
+        long = "report-unchanged-ratio",
+        value_name = "THRESHOLD",
+        help = "Warn about collections in which less than this fraction of the documents changed (e.g. 0.5)"
+    )]
+    pub report_unchanged_ratio: Option<f64>,
+    #[arg(
+        long = "field-sample",
+        value_name = "N",
+        help = "Print up to N example repairs per field path at the end of the run"
+    )]
+    pub field_sample: Option<usize>,
+    #[arg(
+        long = "report-format",
+        value_enum,
+        default_value_t = ReportFormat::Text,
+        help = "Format of the --stats report"
+    )]
+    pub report_format: ReportFormat,
+    #[arg(
+        long = "output-format",
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Output format of --stats (json overrides --report-format)"
+    )]
+    pub output_format: OutputFormat,
+    #[arg(
+        long = "report-top-fields",
+        default_value = "10",
+        help = "Number of most frequently broken fields listed in the Markdown report"
+    )]
+    pub report_top_fields: usize,
+    #[arg(
+        long = "field-stats-file",
+        value_name = "FILE",
+        help = "Write per-field repair statistics to this CSV file"
+    )]
+    pub field_stats_file: Option<PathBuf>,
+    #[arg(
+        long = "stream-report",
+        value_name = "PATH",
+        help = "Write progress, changed documents and finished collections as JSON Lines to this file or named pipe (FIFO)"
+    )]
+    pub stream_report: Option<PathBuf>,
+    #[arg(
+        long = "audit-collection",
+        value_name = "DB.COLLECTION",
+        value_parser = parse_namespace,
+        help = "Insert the changes of every repaired document into this collection"
+    )]
+    pub audit_collection: Option<(String, String)>,
+    #[arg(
+        long = "audit-log-file",
+        value_name = "PATH",
+        help = "Append the changes of every repaired document to this JSON Lines file, which can be replayed"
+    )]
+    pub audit_log_file: Option<PathBuf>,
+    #[arg(
+        long = "log-rotate-max-bytes",
+        value_name = "N",
+        requires = "audit_log_file",
+        help = "Rename the --audit-log-file to <name>.<timestamp>.json and start a new one once it exceeds N bytes"
+    )]
+    pub log_rotate_max_bytes: Option<u64>,
+    #[arg(
+        long = "log-rotate-keep",
+        value_name = "N",
+        requires = "log_rotate_max_bytes",
+        help = "Delete the oldest rotated audit files when more than N exist"
+    )]
+    pub log_rotate_keep: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExplainVerbosity {
+    #[value(alias = "queryPlanner")]
+    QueryPlanner,
+    #[value(alias = "executionStats")]
+    ExecutionStats,
+    #[value(alias = "allPlansExecution")]
+    AllPlansExecution,
+}
+
+impl ExplainVerbosity {
+    fn name(self) -> &'static str {
+        match self {
+            Self::QueryPlanner => "queryPlanner",
+            Self::ExecutionStats => "executionStats",
+            Self::AllPlansExecution => "allPlansExecution",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReadConcernLevel {
+    /// The server's default read concern.
+    Default,
+    Majority,
+    Snapshot,
 }
 
-fn fix_string(
-    doc: &bson::RawDocument,
-    key: &str,
-    elem: &bson::raw::RawElement,
-    start: usize,
-    confirm: bool,
-) -> eyre::Result<(bool, String)> {
-    let bytes = doc.as_bytes();
-
-    let key_start = start + 4 + 1;
-    let raw_key = &bytes[key_start..key_start + key.len()];
-    assert_eq!(key, String::from_utf8_lossy(raw_key).to_string());
-
-    let value_start = key_start + key.len();
-    let raw_value = &bytes[value_start + 4 + 1..value_start + elem.len()];
-    let old_value_utf8 = String::from_utf8_lossy(raw_value).to_string();
-    // println!("{key: >20} => {:#02x?}", raw_value);
-    // println!(
-    //     "{key: >20} => [utf8]{:?}",
-    //     String::from_utf8_lossy(raw_value)
-    // );
-    let value_utf16 =
-        String::from_utf16_lossy(&raw_value.into_iter().map(|v| *v as u16).collect::<Vec<_>>());
-    let new_value_utf8_bytes = value_utf16.as_bytes();
-    let new_value_utf8 = String::from_utf8_lossy(new_value_utf8_bytes).to_string();
-    // println!("{key: >20} => [utf16]{:?}", value_utf16);
-    // println!(
-    //     "{key: >20} => [utf8]{:?}",
-    //     String::from_utf8_lossy(value_utf8)
-    // );
-
-    let hex_id = doc
-        .get_object_id("_id")
-        .ok()
-        .map(bson::oid::ObjectId::to_hex);
-
-    // let prompt = format!(
-    //     "[{}][{key}] {old_value_utf8:?} => {new_value_utf8:?}",
-    //     hex_id.as_deref().unwrap_or(""),
-    // );
-    let prompt = format!(
-        "[{}][{key}] {}",
-        hex_id.as_deref().unwrap_or(""),
-        Comparison::new(&old_value_utf8, &new_value_utf8)
-    );
-    let confirmation = if confirm {
-        Confirm::new().with_prompt(&prompt).interact().unwrap()
-    } else {
-        true
-    };
+impl ReadConcernLevel {
+    fn read_concern(self) -> Option<ReadConcern> {
+        match self {
+            Self::Default => None,
+            Self::Majority => Some(ReadConcern::majority()),
+            Self::Snapshot => Some(ReadConcern::snapshot()),
+        }
+    }
+}
 
-    Ok(if confirmation {
-        println!("{}", &prompt);
-        (true, new_value_utf8)
-    } else {
-        (false, old_value_utf8)
-    })
-}
-
-fn fix_document(
-    doc: &bson::RawDocument,
-    new_doc: &mut bson::RawDocumentBuf,
-    confirm: bool,
-) -> eyre::Result<bool> {
-    let mut changed = false;
-    let mut start = 0;
-    for elem in doc.iter_elements() {
-        let elem = elem?;
-        let key = elem.key();
-        let value = elem.value();
-
-        match elem.element_type() {
-            bson::spec::ElementType::EmbeddedDocument => {
-                let subdoc = doc.get_document(key)?;
-                let mut new_subdoc = bson::RawDocumentBuf::new();
-                fix_document(subdoc, &mut new_subdoc, confirm)?;
-                new_doc.append(key, new_subdoc);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WireCompressor {
+    Zstd,
+    Snappy,
+    Zlib,
+}
+
+impl WireCompressor {
+    fn compressor(self) -> Compressor {
+        match self {
+            Self::Zstd => Compressor::Zstd { level: None },
+            Self::Snappy => Compressor::Snappy,
+            Self::Zlib => Compressor::Zlib { level: None },
+        }
+    }
+
+    /// First server version supporting the compressor.
+    fn min_server_version(self) -> (i32, i32) {
+        match self {
+            Self::Zstd => (4, 2),
+            Self::Snappy => (3, 4),
+            Self::Zlib => (3, 6),
+        }
+    }
+}
+
+/// Warn if the server is too old for `compressor`, in which case
+/// messages are silently sent uncompressed.
+async fn check_compressor(client: &Client, compressor: WireCompressor) -> eyre::Result<()> {
+    let build_info = client
+        .database("admin")
+        .run_command(bson::doc! { "buildInfo": 1 })
+        .await?;
+    let version = build_info.get_array("versionArray")?;
+    let part = |idx: usize| version.get(idx).and_then(bson::Bson::as_i32).unwrap_or(0);
+    let (major, minor) = compressor.min_server_version();
+    if (part(0), part(1)) < (major, minor) {
+        eprintln!(
+            "WARNING: server version {} does not support {} compression (requires {major}.{minor}), messages are sent uncompressed",
+            build_info.get_str("version").unwrap_or("unknown"),
+            format!("{compressor:?}").to_lowercase()
+        );
+    }
+    Ok(())
+}
+
+/// Warn about the restrictions of Atlas Serverless instances, which report a
+/// writable primary without a replica set name.
+async fn check_deployment(client: &Client, hosts: &[String]) -> eyre::Result<()> {
+    let hello = client
+        .database("admin")
+        .run_command(bson::doc! { "hello": 1 })
+        .await?;
+    let writable_primary = hello.get_bool("isWritablePrimary").unwrap_or(false);
+    let is_atlas = hosts.iter().any(|host| {
+        host.split(':')
+            .next()
+            .unwrap_or(host)
+            .ends_with(".mongodb.net")
+    });
+    if writable_primary && !hello.contains_key("setName") && is_atlas {
+        eprintln!(
+            "WARNING: connected to Atlas Serverless, which supports neither transactions nor the oplog (--oplog-replay); keep the connection pool small (maxPoolSize in the URI)"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Apply changes previously written with --dry-run-output-json
+    ApplyChanges {
+        #[arg(long = "input-json", help = "JSON file with the changes to apply")]
+        input_json: PathBuf,
+    },
+    /// Re-apply the changes of an exported audit log to another database
+    Replay {
+        #[arg(
+            long = "audit-log",
+            help = "--audit-log-file, or JSON Lines export of the --audit-collection (e.g. written by mongoexport)"
+        )]
+        audit_log: PathBuf,
+        #[arg(long = "dest-database", help = "database to apply the changes to")]
+        dest_database: String,
+    },
+    /// Repair known-corrupt documents in a temporary database and check the results
+    SelfTest {
+        #[arg(
+            long = "mongodb-uri",
+            help = "MongoDB connection URI of the deployment to create the temporary database in"
+        )]
+        mongodb_uri: String,
+    },
+}
+
+/// Corrupt string values inserted by the self-test, with their expected repairs.
+const SELF_TEST_CASES: [(&str, &[u8], &str); 4] = [
+    ("latin1", b"caf\xe9", "caf\u{e9}"),
+    (
+        "latin1_multiple",
+        b"na\xefve \xfcber",
+        "na\u{ef}ve \u{fc}ber",
+    ),
+    ("valid", "caf\u{e9}".as_bytes(), "caf\u{e9}"),
+    ("ascii", b"plain", "plain"),
+];
+
+/// Options the self-test repairs with, set explicitly where a different default of
+/// [`RepairOptions`] (e.g. a lower ASCII ratio threshold) would skip [`SELF_TEST_CASES`].
+fn self_test_options() -> RepairOptions {
+    RepairOptions::default()
+        .field_ascii_ratio_threshold(1.0)
+        .quiet(true)
+        .build()
+}
+
+/// Insert the [`SELF_TEST_CASES`] into `collection`, repair it and check the repaired values.
+async fn run_self_test(collection: &mongodb::Collection<bson::RawDocumentBuf>) -> eyre::Result<()> {
+    let docs = SELF_TEST_CASES
+        .iter()
+        .map(|(field, raw_bytes, _)| make_corrupt_doc(field, raw_bytes));
+    collection.insert_many(docs).await?;
+
+    let report = fix_collection(collection.clone(), &self_test_options()).await?;
+    println!("self-test: {}", report.metrics);
+
+    let mut failures = 0;
+    for (field, _, expected) in SELF_TEST_CASES {
+        let doc = collection
+            .find_one(bson::doc! { field: { "$exists": true } })
+            .await?
+            .ok_or_else(|| eyre::eyre!("document of case {field} is missing"))?;
+        match doc.get_str(field) {
+            Ok(value) if value == expected => println!("self-test: {field: <16} ok"),
+            Ok(value) => {
+                failures += 1;
+                eprintln!("self-test: {field: <16} FAILED: expected {expected:?}, found {value:?}");
             }
-            bson::spec::ElementType::Array => {
-                let array = doc.get_array(key)?;
-                let mut new_array = bson::raw::RawArrayBuf::new();
-                for item in array.into_iter() {
-                    match item? {
-                        bson::raw::RawBsonRef::Document(subdoc) => {
-                            let mut new_subdoc = bson::RawDocumentBuf::new();
-                            fix_document(subdoc, &mut new_subdoc, confirm)?;
-                            new_array.push(new_subdoc);
-                        }
-                        bson::raw::RawBsonRef::String(value) => {
-                            // this is not good enough yet
-                            new_array.push(bson::RawBson::String(
-                                String::from_utf8_lossy(value.as_bytes()).to_string(),
-                            ));
-                        }
-                        other => {
-                            new_array.push(other.to_raw_bson());
-                        }
-                    }
-                }
-                new_doc.append(key, new_array);
+            Err(err) => {
+                failures += 1;
+                eprintln!("self-test: {field: <16} FAILED: {err}");
             }
-            bson::spec::ElementType::String => {
-                if let Err(bson::raw::Error {
-                    kind: bson::raw::ErrorKind::Utf8EncodingError(_err),
-                    ..
-                }) = value
-                {
-                    let (fixed, value) = fix_string(doc, key, &elem, start, confirm)?;
-                    new_doc.append(key, bson::raw::RawBson::String(value));
-                    if fixed {
-                        changed = true;
-                    }
-                } else {
-                    new_doc.append(key, value?.to_raw_bson());
-                }
+        }
+    }
+    if failures > 0 {
+        eyre::bail!(
+            "{failures} of {} self-test cases failed",
+            SELF_TEST_CASES.len()
+        );
+    }
+    Ok(())
+}
+
+/// Ping the deployment of `client`, retrying up to --connection-retry attempts with exponential backoff.
+async fn ping(client: &Client, options: &Options) -> eyre::Result<()> {
+    let mut delay = std::time::Duration::from_millis(options.connection_retry_delay_ms);
+    let mut attempt = 1;
+    loop {
+        match client
+            .database("admin")
+            .run_command(bson::doc! { "ping": 1 })
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt < options.connection_retry => {
+                eprintln!(
+                    "ping failed (attempt {attempt} of {}): {err}, retrying in {delay:.2?}",
+                    options.connection_retry
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
             }
-            _other => {
-                new_doc.append(key, value?.to_raw_bson());
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Run the self-test in a temporary database of the deployment at `uri`, which is dropped afterwards.
+async fn self_test(uri: &str) -> eyre::Result<()> {
+    validate_connection_string(uri).await?;
+    let client = Client::with_options(ClientOptions::parse(uri).await?)?;
+    client
+        .database("admin")
+        .run_command(bson::doc! { "ping": 1 })
+        .await?;
+    let database_name = format!(
+        "mongo_repair_utf8_self_test_{}",
+        bson::DateTime::now().timestamp_millis()
+    );
+    println!("self-test: using temporary database {database_name}");
+    let db = client.database(&database_name);
+    let result = run_self_test(&db.collection("documents")).await;
+    db.drop().await?;
+    result?;
+    println!("self-test: passed");
+    Ok(())
+}
+
+impl From<Options> for RepairOptions {
+    fn from(options: Options) -> Self {
+        let mut repair_options = RepairOptions::default()
+            .encoding(options.encoding)
+            .dry_run(options.dry_run || options.dry_run_count)
+            .count_only(options.dry_run_count)
+            .concurrency(options.concurrency)
+            .parallel_documents(options.parallel_documents)
+            .randomize_order(options.randomize_order)
+            .two_pass_scan(options.two_pass_scan)
+            .timing_profile(options.timing_profile)
+            .repair_embedded_json(options.repair_embedded_json)
+            .normalize_whitespace(options.normalize_whitespace)
+            .graceful_degradation(options.graceful_degradation)
+            .validate_utf8_strictly(options.validate_utf8_strictly)
+            .test_idempotency(options.test_idempotency)
+            .report_raw_bytes(options.report_raw_bytes)
+            .max_hex_bytes(options.max_hex_bytes)
+            .noop_writes(options.noop_writes)
+            .verify_before_repair(options.verify_before_repair)
+            .validate_post_repair(options.validate_post_repair)
+            .bulk_find_batch(options.bulk_find_batch)
+            .field_entropy_threshold(options.field_entropy_threshold)
+            .field_ascii_ratio_threshold(options.field_ascii_ratio_threshold)
+            .max_retries(options.max_retries)
+            .collection_validator_check(options.collection_validator_check)
+            .repair_keys(options.repair_keys)
+            .rename_repaired_keys(options.rename_repaired_keys)
+            .max_document_size(options.max_doc_size_mb * 1024 * 1024)
+            .strict_size(options.strict_size)
+            .quiet(options.quiet || options.dry_run_count)
+            .skip_unchanged(options.skip_unchanged)
+            .only_changed(options.only_changed)
+            .verbose(options.verbose)
+            .abort_on_error(options.abort_on_error)
+            .ignore_fields_with_errors(options.ignore_fields_with_errors)
+            .split_encoding(options.split_encoding)
+            .include_binary_fields(options.include_binary_fields)
+            .fix_binary_fields(options.fix_binary_fields)
+            .diff_context_lines(options.diff_context_lines)
+            .progress_interval(options.progress_interval)
+            .max_errors_per_collection(options.max_errors_per_collection)
+            .min_changed_fields(options.min_changed_fields);
+        if options.no {
+            repair_options = repair_options.confirmation(NeverConfirm);
+        } else if options.interactive || options.confirm == Some(true) {
+            repair_options = repair_options.confirmation(InteractiveConfirm {
+                default: options.default_confirm,
+            });
+        }
+        #[cfg(debug_assertions)]
+        if let Some(every) = options.simulate_write_failure {
+            repair_options = repair_options.simulate_write_failure(every);
+        }
+        if let Some(field) = options.update_timestamps {
+            repair_options = repair_options.update_timestamp_field(field);
+        }
+        if let Some(style) = options.normalize_legacy_uuid {
+            repair_options = repair_options.normalize_legacy_uuid(style);
+        }
+        if let Some(field) = options.deduplicate_repaired {
+            repair_options = repair_options.deduplicate_repaired(field, options.dedup_action);
+        }
+        if let Some(strategy) = options.split_large_documents {
+            repair_options = repair_options.split_strategy(strategy);
+        }
+        if let Some(pipeline) = options.pipeline {
+            if pipeline
+                .iter()
+                .any(|stage| stage.contains_key("$out") || stage.contains_key("$merge"))
+            {
+                eprintln!("WARNING --pipeline contains $out or $merge, which conflict with the repair's own writes");
             }
+            repair_options = repair_options.pipeline(pipeline);
+        }
+        if let Some(projection) = options.document_projection {
+            repair_options = repair_options.projection(projection);
+        }
+        if let Some(read_concern) = options.read_concern.read_concern() {
+            repair_options = repair_options.read_concern(read_concern);
+        }
+        if let Some(hint) = options.hint {
+            repair_options = repair_options.hint(hint);
+        }
+        if let Some(max_time_ms) = options.cursor_max_time_ms {
+            repair_options =
+                repair_options.cursor_max_time(std::time::Duration::from_millis(max_time_ms));
+        }
+        if let Some(tolerance_ms) = options.replication_lag_tolerance_ms {
+            repair_options = repair_options
+                .replication_lag_tolerance(std::time::Duration::from_millis(tolerance_ms));
+        }
+        if let Some(pattern) = options.repair_regex {
+            repair_options = repair_options.repair_regex(pattern);
+        }
+        if let Some(pattern) = options.skip_regex {
+            repair_options = repair_options.skip_regex(pattern);
+        }
+        if !options.field_type_whitelist.is_empty() {
+            repair_options = repair_options.field_types(options.field_type_whitelist);
+        }
+        if let Some(threshold) = options.report_unchanged_ratio {
+            repair_options = repair_options.min_changed_ratio(threshold);
+        }
+        if let Some(ratio) = options.max_replacement_ratio {
+            repair_options = repair_options.max_replacement_ratio(ratio);
+        }
+        if let Some(field) = options.version_field {
+            repair_options = repair_options.version_field(field);
+        }
+        for (old_path, new_path) in options.rename_fields {
+            repair_options = repair_options.rename_field(old_path, new_path);
+        }
+        for (path, min) in options.field_min_lengths {
+            repair_options = repair_options.field_min_length(path, min);
+        }
+        for (path, max) in options.field_max_lengths {
+            repair_options = repair_options.field_max_length(path, max);
         }
-        start += 1 + key.len() + 1 + elem.len();
+        repair_options =
+            repair_options.constraint_violation_action(options.constraint_violation_action);
+        if let Some(name) = options.start_from_collection {
+            repair_options = repair_options.start_from_collection(name);
+        }
+        if let Some(name) = options.stop_after_collection {
+            repair_options = repair_options.stop_after_collection(name);
+        }
+        if let Some(secs) = options.collection_timeout {
+            repair_options =
+                repair_options.collection_timeout(std::time::Duration::from_secs(secs));
+        }
+        repair_options = repair_options.collection_order(options.collection_order);
+        if !options.prioritize_collections.is_empty() {
+            repair_options = repair_options.prioritize_collections(options.prioritize_collections);
+        }
+        if let Some(hook) = options.pre_repair_hook {
+            repair_options = repair_options.pre_repair_hook(hook);
+        }
+        if let Some(hook) = options.post_repair_hook {
+            repair_options = repair_options.post_repair_hook(hook);
+        }
+        if let Some(shard_key) = options.shard_key {
+            repair_options = repair_options.shard_key(shard_key);
+        }
+        if let Some(updated_by) = options.updated_by {
+            repair_options = repair_options.updated_by(updated_by);
+        }
+        repair_options.build()
     }
-    Ok(changed)
 }
 
-async fn fix_collection(
-    collection: mongodb::Collection<bson::RawDocumentBuf>,
-    confirm: bool,
-    dry_run: bool,
-) -> eyre::Result<()> {
-    let mut cursor = collection.find(bson::doc! {}).await?;
-    while let Some(raw_doc) = cursor.try_next().await? {
-        let mut new_raw_doc = bson::raw::RawDocumentBuf::new();
+/// Parse a (possibly extended) JSON document argument.
+fn parse_document(value: &str) -> Result<bson::Document, String> {
+    let json: serde_json::Value = serde_json::from_str(value).map_err(|err| err.to_string())?;
+    match bson::Bson::try_from(json).map_err(|err| err.to_string())? {
+        bson::Bson::Document(document) => Ok(document),
+        other => Err(format!("expected a JSON object, got {other}")),
+    }
+}
 
-        let id = raw_doc.get_object_id("_id").ok();
+/// Aggregation stages, parsed from a single argument (unlike a `Vec` argument).
+type Pipeline = Vec<bson::Document>;
 
-        println!(
-            "collection = {: <20} id = {: <30}",
-            collection.name(),
-            id.map(bson::oid::ObjectId::to_hex).as_deref().unwrap_or("")
-        );
+/// Parse a (possibly extended) JSON array of aggregation stages.
+fn parse_pipeline(value: &str) -> Result<Pipeline, String> {
+    let json: serde_json::Value = serde_json::from_str(value).map_err(|err| err.to_string())?;
+    let bson::Bson::Array(stages) = bson::Bson::try_from(json).map_err(|err| err.to_string())?
+    else {
+        return Err("expected a JSON array of stages".to_string());
+    };
+    stages
+        .into_iter()
+        .map(|stage| match stage {
+            bson::Bson::Document(stage) => Ok(stage),
+            other => Err(format!("expected a stage document, got {other}")),
+        })
+        .collect()
+}
 
-        let changed = fix_document(&*raw_doc, &mut new_raw_doc, confirm)?;
+/// Parse a projection of top-level fields.
+fn parse_projection(value: &str) -> Result<bson::Document, String> {
+    let projection = parse_document(value)?;
+    if let Some(key) = projection.keys().find(|key| key.contains('.')) {
+        return Err(format!(
+            "nested field {key:?} cannot be projected, use its top-level field"
+        ));
+    }
+    Ok(projection)
+}
 
-        let doc = raw_doc.to_document();
-        let fixed_doc = new_raw_doc.clone().to_document();
+/// Parse a `db.collection` namespace.
+fn parse_namespace(value: &str) -> Result<(String, String), String> {
+    match value.split_once('.') {
+        Some((db, collection)) if !db.is_empty() && !collection.is_empty() => {
+            Ok((db.to_string(), collection.to_string()))
+        }
+        _ => Err(format!("expected DB.COLLECTION, got {value:?}")),
+    }
+}
 
-        match (&doc, &fixed_doc) {
-            (Ok(doc), Ok(fixed_doc)) => {
-                // print!("{}", Comparison::new(&doc, &fixed_doc));
-                if doc != fixed_doc {
-                    print!("{}", Comparison::new(&doc, &fixed_doc));
-                }
-            }
-            (Err(_doc), Ok(_fixed_doc)) => {
-                // fine
+/// Parse an `old=new` pair of field paths.
+fn parse_rename(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        }
+        _ => Err(format!("expected OLD=NEW, got {value:?}")),
+    }
+}
+
+/// Parse a `PATH=N` length constraint.
+fn parse_length_constraint(value: &str) -> Result<(String, usize), String> {
+    match value.split_once('=') {
+        Some((path, len)) if !path.is_empty() => len
+            .parse()
+            .map(|len| (path.to_string(), len))
+            .map_err(|err| format!("invalid length {len:?}: {err}")),
+        _ => Err(format!("expected PATH=N, got {value:?}")),
+    }
+}
+
+/// Parse the name of a string-like BSON type.
+fn parse_field_type(name: &str) -> Result<bson::spec::ElementType, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "string" => Ok(bson::spec::ElementType::String),
+        "symbol" => Ok(bson::spec::ElementType::Symbol),
+        "javascript" => Ok(bson::spec::ElementType::JavaScriptCode),
+        _ => Err(format!(
+            "unsupported field type {name:?} (expected one of string, symbol, javascript)"
+        )),
+    }
+}
+
+/// Check `uri` for common mistakes before connecting.
+///
+/// Fails if the URI cannot be parsed or names no host, and warns about
+/// credentials sent without TLS to remote hosts or authenticated against
+/// the default database instead of `admin`.
+async fn validate_connection_string(uri: &str) -> eyre::Result<()> {
+    let client_options = ClientOptions::parse(uri)
+        .await
+        .map_err(|err| eyre::eyre!("invalid connection URI: {err}"))?;
+    if client_options.hosts.is_empty() {
+        eyre::bail!("invalid connection URI: no host");
+    }
+    let Some(credential) = &client_options.credential else {
+        return Ok(());
+    };
+    if credential.username.is_none() {
+        return Ok(());
+    }
+    let is_local = client_options.hosts.iter().all(|address| {
+        matches!(
+            address,
+            ServerAddress::Tcp { host, .. } if matches!(host.as_str(), "localhost" | "127.0.0.1" | "::1")
+        )
+    });
+    let tls = matches!(client_options.tls, Some(mongodb::options::Tls::Enabled(_)));
+    if !is_local && !tls {
+        eprintln!(
+            "WARNING: credentials are sent to a remote host without TLS (add tls=true to the URI)"
+        );
+    }
+    // the driver authenticates against the default database unless authSource is given
+    if !uri.contains("authSource=") {
+        if let Some(database) = client_options.default_database.as_deref() {
+            if database != "admin" {
+                eprintln!(
+                    "WARNING: authenticating against database {database:?}, add authSource=admin to the URI if the user is defined there"
+                );
             }
-            (doc, fixed_doc) => {
-                println!("{:?}", doc);
-                println!("{:?}", fixed_doc);
+        }
+    }
+    Ok(())
+}
+
+/// Apply the authentication flags to the credential parsed from the connection URI.
+fn apply_credential(options: &Options, client_options: &mut ClientOptions) {
+    let Some(mechanism) = &options.auth_mechanism else {
+        return;
+    };
+    let credential = client_options
+        .credential
+        .get_or_insert_with(Credential::default);
+    credential.mechanism = Some(mechanism.clone());
+    if *mechanism == AuthMechanism::Gssapi {
+        credential.source = Some("$external".to_string());
+        credential
+            .mechanism_properties
+            .get_or_insert_with(bson::Document::new)
+            .insert("SERVICE_NAME", &options.kerberos_service_name);
+    }
+    if *mechanism == AuthMechanism::MongoDbAws {
+        // without explicit keys, the driver uses the environment and instance metadata
+        if let Some(access_key_id) = &options.aws_access_key_id {
+            credential.username = Some(access_key_id.clone());
+            credential.password = options.aws_secret_access_key.clone();
+        }
+        if let Some(session_token) = &options.aws_session_token {
+            credential
+                .mechanism_properties
+                .get_or_insert_with(bson::Document::new)
+                .insert("AWS_SESSION_TOKEN", session_token);
+        }
+    }
+}
+
+/// Path of the output file `path`, with a `.gz` suffix if --compress-output is set.
+fn output_path(options: &Options, path: &Path) -> PathBuf {
+    if options.compress_output {
+        let mut path = path.as_os_str().to_owned();
+        path.push(".gz");
+        PathBuf::from(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Create the output file `path`, gzip-compressed if --compress-output is set.
+///
+/// The gzip trailer is written when the returned writer is dropped.
+fn create_output(options: &Options, path: &Path) -> eyre::Result<Box<dyn Write + Send>> {
+    let file = std::fs::File::create(output_path(options, path))?;
+    Ok(output_writer(options, file))
+}
+
+/// Create a document export at `path`, which is synced to disk by [`RepairRunGuard`].
+fn create_export(
+    options: &Options,
+    path: &Path,
+    format: ExportFormat,
+) -> eyre::Result<DocumentExport> {
+    let file = std::fs::File::create(output_path(options, path))?;
+    let handle = file.try_clone()?;
+    Ok(DocumentExport::new(output_writer(options, file), format).sync_file(handle))
+}
+
+fn output_writer(options: &Options, file: std::fs::File) -> Box<dyn Write + Send> {
+    if options.compress_output {
+        let level = flate2::Compression::new(options.compression_level);
+        Box::new(std::io::BufWriter::new(GzEncoder::new(file, level)))
+    } else {
+        Box::new(std::io::BufWriter::new(file))
+    }
+}
+
+fn write_changes_json(options: &Options, path: &Path, changes: &[&RepairDiff]) -> eyre::Result<()> {
+    let mut file = create_output(options, path)?;
+    serde_json::to_writer_pretty(&mut file, changes)?;
+    file.flush()?;
+    println!(
+        "wrote {} proposed document changes to {}",
+        changes.len(),
+        output_path(options, path).display()
+    );
+    Ok(())
+}
+
+/// Write the per-field statistics collected for --field-stats-file.
+fn write_field_stats(options: &Options, repair_options: &RepairOptions) -> eyre::Result<()> {
+    let (Some(path), Some(field_stats)) = (&options.field_stats_file, &repair_options.field_stats)
+    else {
+        return Ok(());
+    };
+    field_stats.write_csv(create_output(options, path)?)?;
+    println!(
+        "wrote field statistics to {}",
+        output_path(options, path).display()
+    );
+    Ok(())
+}
+
+/// Databases skipped by --all-databases.
+const SYSTEM_DATABASES: [&str; 3] = ["admin", "config", "local"];
+
+/// Repair every database but the [`SYSTEM_DATABASES`], --parallel-databases at a time,
+/// and report their collections as `database.collection`.
+async fn repair_all_databases(
+    client: &Client,
+    options: &Options,
+    repair_options: RepairOptions,
+) -> eyre::Result<()> {
+    let started = std::time::Instant::now();
+    let started_at = bson::DateTime::now();
+    let mut database_names = client.list_database_names().await?;
+    database_names.retain(|name| !SYSTEM_DATABASES.contains(&name.as_str()));
+    println!("repairing {} databases", database_names.len());
+
+    let guard = RepairRunGuard::new(&repair_options);
+    let run = stream::iter(database_names)
+        .map(|name| {
+            let mut session = RepairSession::new(client.database(&name), repair_options.clone());
+            async move {
+                session.repair_database(&options.collection_names).await?;
+                Ok::<_, eyre::Report>(session.into_report())
             }
+        })
+        .buffered(options.parallel_databases.max(1))
+        .try_collect::<Vec<_>>();
+    let reports = tokio::select! {
+        reports = run => reports?,
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("interrupted, flushing outputs");
+            guard.shutdown().await?;
+            eyre::bail!("interrupted");
         }
+    };
+    guard.shutdown().await?;
 
-        if !dry_run && changed {
-            // replace the document
-            if let Ok(id) = raw_doc.get_object_id("_id") {
+    let collections = reports
+        .into_iter()
+        .flat_map(|report| {
+            let database = report.database;
+            report.collections.into_iter().map(move |mut collection| {
+                collection.collection = format!("{database}.{}", collection.collection);
                 collection
-                    .find_one_and_replace(bson::doc! {"_id": id}, new_raw_doc)
-                    .await?;
-                println!(
-                    "collection = {: <20} id = {: <30} REPLACED",
-                    collection.name(),
-                    id.to_hex()
-                );
+            })
+        })
+        .collect();
+    let report = RunReport::new(
+        "*",
+        &repair_options,
+        started_at,
+        started.elapsed(),
+        collections,
+    );
+    write_field_stats(options, &repair_options)?;
+    finish_run(options, &report)
+}
+
+fn finish_run(options: &Options, report: &RunReport) -> eyre::Result<()> {
+    if let Some(path) = &options.dry_run_output_json {
+        let changes: Vec<_> = report
+            .collections
+            .iter()
+            .flat_map(|collection| &collection.changes)
+            .collect();
+        write_changes_json(options, path, &changes)?;
+    }
+    if let Some(path) = &options.export_broken_only {
+        let exported: usize = report
+            .collections
+            .iter()
+            .map(|collection| collection.changes.len())
+            .sum();
+        println!(
+            "exported {exported} broken documents to {}",
+            output_path(options, path).display()
+        );
+    }
+    if let Some(n) = options.field_sample {
+        for (path, examples) in report.field_samples(n) {
+            println!("field = {path}");
+            for change in examples {
+                println!("  {:?} => {:?}", change.old_value, change.new_value);
+            }
+        }
+    }
+    if options.char_encoding_report {
+        for collection in &report.collections {
+            println!(
+                "collection = {: <20} PROBABLE SOURCE ENCODINGS",
+                collection.collection
+            );
+            for (encoding, count) in collection.source_encodings() {
+                println!("  {encoding: <20} -> {count}");
+            }
+        }
+    }
+    if options.stats {
+        match (options.output_format, options.report_format) {
+            (OutputFormat::Json, _) => {
+                let collections: Vec<_> = report
+                    .collections
+                    .iter()
+                    .map(|collection| {
+                        serde_json::json!({
+                            "collection": collection.collection,
+                            "metrics": collection.metrics,
+                        })
+                    })
+                    .collect();
+                let stats = serde_json::json!({
+                    "database": report.database,
+                    "metrics": report.metrics,
+                    "collections": collections,
+                });
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            }
+            (OutputFormat::Text, ReportFormat::Text) => print!("{report}"),
+            (OutputFormat::Text, ReportFormat::Markdown) => {
+                print!("{}", report.to_markdown(options.report_top_fields));
             }
         }
+    } else {
+        println!("{}", report.metrics);
     }
     Ok(())
 }
@@ -210,42 +1564,313 @@ async fn fix_collection(
 async fn main() -> eyre::Result<()> {
     color_eyre::install()?;
     let options = Options::parse();
+    if let Some(Command::SelfTest { mongodb_uri }) = &options.command {
+        return self_test(mongodb_uri).await;
+    }
+    if options.confirm.is_some() {
+        eprintln!("WARNING: --confirm is deprecated, use --interactive or --yes");
+    }
+    let mut repair_options = RepairOptions::from(options.clone());
+    let started = std::time::Instant::now();
+    let started_at = bson::DateTime::now();
+    if let Some(path) = &options.schema_file {
+        let schema: serde_json::Value =
+            serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|err| eyre::eyre!("invalid schema {}: {err}", path.display()))?;
+        repair_options = repair_options.schema(validator);
+    }
+    if let Some(path) = &options.export_broken_only {
+        let format = match options.output_format {
+            OutputFormat::Json => ExportFormat::Json,
+            OutputFormat::Text => ExportFormat::Bson,
+        };
+        repair_options = repair_options.export_broken(create_export(&options, path, format)?);
+    }
+    if let Some(path) = &options.output_bson {
+        let output = create_export(&options, path, ExportFormat::Bson)?;
+        repair_options = repair_options.output_bson(output);
+    }
+    match (&options.custom_encoding_file, options.encoding) {
+        (Some(path), Encoding::Custom) => {
+            repair_options = repair_options.custom_encoding(CustomEncoding::load(path)?);
+        }
+        (None, Encoding::Custom) => {
+            eyre::bail!("--encoding custom requires --custom-encoding-file")
+        }
+        (Some(_), _) => eyre::bail!("--custom-encoding-file requires --encoding custom"),
+        (None, _) => {}
+    }
+    match (&options.field_transform_script, options.encoding) {
+        (Some(path), Encoding::Script) => {
+            let script = TransformScript::from_file(path)?.timeout(
+                std::time::Duration::from_millis(options.field_transform_timeout_ms),
+            );
+            repair_options = repair_options.transform_script(script);
+        }
+        (None, Encoding::Script) => {
+            eyre::bail!("--encoding script requires --field-transform-script")
+        }
+        (Some(_), _) => eyre::bail!("--field-transform-script requires --encoding script"),
+        (None, _) => {}
+    }
+    if let Some(path) = &options.field_blocklist_file {
+        repair_options = repair_options.field_blocklist(FieldBlocklist::load(path)?);
+    }
+    if options.field_stats_file.is_some() {
+        repair_options = repair_options.field_stats(FieldStats::default());
+    }
+    if let Some(expression) = &options.document_filter_jq {
+        repair_options = repair_options.jq_filter(JqFilter::new(expression)?);
+    }
+    if let Some(path) = &options.stream_report {
+        eprintln!(
+            "opening report stream {} (a FIFO waits for its reader)",
+            path.display()
+        );
+        repair_options = repair_options.report_stream(ReportStream::open(path).await?);
+    }
+
+    let (connection_uri, uri_source) = match (
+        &options.vault_uri,
+        &options.vault_secret_path,
+        &options.aws_secret_id,
+        &options.gcp_secret_name,
+    ) {
+        (Some(vault_uri), Some(secret_path), _, _) => {
+            let uri = read_vault_secret(&options, vault_uri, secret_path).await?;
+            (uri, format!("Vault secret {secret_path}"))
+        }
+        (_, _, Some(secret_id), _) => {
+            let uri = read_aws_secret(&options, secret_id).await?;
+            (uri, format!("AWS secret {secret_id}"))
+        }
+        (_, _, _, Some(name)) => (read_gcp_secret(name).await?, format!("GCP secret {name}")),
+        _ => {
+            let Some(uri) = options.connection_uri.clone() else {
+                eyre::bail!("no connection URI specified");
+            };
+            (uri.clone(), uri)
+        }
+    };
 
-    let client = Client::with_uri_str(&options.connection_uri).await?;
+    validate_connection_string(&connection_uri).await?;
+    let mut client_options = ClientOptions::parse(&connection_uri).await?;
+    apply_credential(&options, &mut client_options);
+    if let Some(compressor) = options.compress_wire {
+        client_options.compressors = Some(vec![compressor.compressor()]);
+    }
+    let client_hosts: Vec<String> = client_options
+        .hosts
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let client = Client::with_options(client_options)?;
 
     // Send a ping to confirm a successful connection
-    client
-        .database("admin")
-        .run_command(bson::doc! { "ping": 1 })
-        .await?;
-    println!("connected to {}", options.connection_uri);
+    ping(&client, &options).await?;
+    println!("connected to {}", uri_source);
+    if let Some(compressor) = options.compress_wire {
+        check_compressor(&client, compressor).await?;
+    }
+    if !options.skip_types_check {
+        check_deployment(&client, &client_hosts).await?;
+    }
+
+    if let Some(Command::Replay {
+        audit_log,
+        dest_database,
+    }) = &options.command
+    {
+        let file = std::io::BufReader::new(std::fs::File::open(audit_log)?);
+        let changes = read_audit_log(file)?;
+        let updated = replay_changes(&client.database(dest_database), &changes).await?;
+        println!("updated {updated} documents");
+        return Ok(());
+    }
+
+    if let Some(path) = &options.audit_log_file {
+        let mut audit_file = AuditFile::open(path)?;
+        if let Some(max_bytes) = options.log_rotate_max_bytes {
+            audit_file = audit_file.max_bytes(max_bytes);
+        }
+        if let Some(keep) = options.log_rotate_keep {
+            audit_file = audit_file.keep(keep);
+        }
+        repair_options = repair_options.audit_file(audit_file);
+    }
+
+    if let Some((audit_db, audit_collection)) = &options.audit_collection {
+        println!(
+            "auditing repairs in {audit_db}.{audit_collection}; to expire old records, create a TTL index: \
+             db.getSiblingDB({audit_db:?}).getCollection({audit_collection:?}).createIndex({{ repairedAt: 1 }}, {{ expireAfterSeconds: 7776000 }})"
+        );
+        repair_options =
+            repair_options.audit_collection(client.database(audit_db).collection(audit_collection));
+    }
+
+    if options.all_databases {
+        return repair_all_databases(&client, &options, repair_options).await;
+    }
 
-    let Some(database_name) = options.database_name else {
+    let Some(database_name) = options.database_name.clone() else {
         eprintln!("no database specified");
         return Ok(());
     };
 
     let db = client.database(&database_name);
 
-    let collection_names: Vec<String> = if !options.collection_names.is_empty() {
-        options.collection_names
-    } else {
-        db.list_collection_names().await?
-    };
+    if let Some(dest_uri) = &options.dest_uri {
+        validate_connection_string(dest_uri).await?;
+        let dest_client = Client::with_options(ClientOptions::parse(dest_uri).await?)?;
+        ping(&dest_client, &options).await?;
+        println!("connected to destination {}", dest_uri);
+        let dest_database = options.dest_database.as_deref().unwrap_or(&database_name);
+        repair_options = repair_options.destination(dest_client.database(dest_database));
+        if let Some(dest_collection) = &options.dest_collection {
+            repair_options = repair_options.destination_collection(dest_collection);
+        }
+    }
+
+    if let Some(Command::ApplyChanges { input_json }) = &options.command {
+        let file = std::io::BufReader::new(std::fs::File::open(input_json)?);
+        let changes: Vec<RepairDiff> = serde_json::from_reader(file)?;
+        let updated = apply_changes(&db, &changes).await?;
+        println!("updated {updated} documents");
+        return Ok(());
+    }
 
-    let confirm = options.confirm.unwrap_or(false);
+    if options.oplog_replay {
+        let mut collections = Vec::new();
+        for col in &options.collection_names {
+            collections.push(fix_oplog(&client, &database_name, col, &repair_options).await?);
+        }
+        let report = RunReport::new(
+            database_name,
+            &repair_options,
+            started_at,
+            started.elapsed(),
+            collections,
+        );
+        return finish_run(&options, &report);
+    }
 
-    stream::iter(collection_names.into_iter())
-        .map(|col| {
-            let db_clone = db.clone();
-            async move {
-                let collection = db_clone.collection::<bson::RawDocumentBuf>(&col);
-                fix_collection(collection, confirm, options.dry_run).await
+    if let Some(verbosity) = options.explain {
+        let collection_names = if options.collection_names.is_empty() {
+            db.list_collection_names().await?
+        } else {
+            options.collection_names.clone()
+        };
+        for name in collection_names {
+            let collection = db.collection::<bson::RawDocumentBuf>(&name);
+            let plan = explain_collection(&collection, &repair_options, verbosity.name()).await?;
+            println!("collection = {: <20} QUERY PLAN", name);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&bson::Bson::Document(plan).into_relaxed_extjson())?
+            );
+        }
+        return Ok(());
+    }
+
+    if options.batch_plan {
+        let collection_names = if options.collection_names.is_empty() {
+            db.list_collection_names().await?
+        } else {
+            options.collection_names.clone()
+        };
+        let mut plans = Vec::new();
+        for name in collection_names {
+            plans.push(BatchRepairPlan::compute(db.collection(&name), &repair_options).await?);
+        }
+        let documents: usize = plans.iter().map(|plan| plan.changes.len()).sum();
+        if documents == 0 {
+            println!("nothing to repair");
+            return Ok(());
+        }
+        match &options.plan_file {
+            Some(path) => {
+                let changes: Vec<_> = plans.iter().flat_map(|plan| &plan.changes).collect();
+                write_changes_json(&options, path, &changes)?;
             }
-        })
-        .buffered(1)
-        .collect::<Vec<_>>()
-        .await;
+            None => plans.iter().for_each(|plan| print!("{plan}")),
+        }
+        let prompt = format!(
+            "apply the changes of {documents} documents in {} collections?",
+            plans.len()
+        );
+        let confirmation = InteractiveConfirm {
+            default: options.default_confirm,
+        };
+        if !options.yes && !confirmation.confirm(&prompt)? {
+            println!("plan not applied");
+            return Ok(());
+        }
+        let mut updated = 0;
+        for plan in &plans {
+            updated += plan.apply(&db).await?;
+        }
+        println!("updated {updated} documents");
+        return Ok(());
+    }
 
-    Ok(())
+    let guard = RepairRunGuard::new(&repair_options);
+    let mut session = RepairSession::new(db, repair_options);
+    let run = async {
+        let mut last_found = std::time::Instant::now();
+        loop {
+            let metrics = session.repair_database(&options.collection_names).await?;
+            let Some(interval) = options.watch_interval else {
+                return Ok::<_, eyre::Report>(());
+            };
+            if metrics.changed_docs + metrics.error_docs > 0 {
+                last_found = std::time::Instant::now();
+            } else if let Some(max_idle) = options.watch_max_idle_ms {
+                let idle = last_found.elapsed();
+                if idle >= std::time::Duration::from_millis(max_idle) {
+                    println!("no broken documents found for {idle:.2?}, stopping");
+                    return Ok(());
+                }
+            }
+            let base = interval * 1000;
+            let delay = if options.watch_jitter > 0 {
+                rand::thread_rng().gen_range(base..base + options.watch_jitter)
+            } else {
+                base
+            };
+            let delay = std::time::Duration::from_millis(delay);
+            println!("scanning again in {delay:.2?} ({metrics})");
+            tokio::time::sleep(delay).await;
+        }
+    };
+    tokio::select! {
+        result = run => {
+            result?;
+        }
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("interrupted, flushing outputs");
+            guard.shutdown().await?;
+            eyre::bail!("interrupted");
+        }
+    }
+    guard.shutdown().await?;
+    write_field_stats(&options, session.options())?;
+    finish_run(&options, &session.into_report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongo_repair_utf8::fix_document;
+
+    #[test]
+    fn self_test_cases_are_repaired() {
+        let options = self_test_options();
+        for (field, raw_bytes, expected) in SELF_TEST_CASES {
+            let doc = make_corrupt_doc(field, raw_bytes);
+            let mut new_doc = bson::RawDocumentBuf::new();
+            fix_document(&doc, &mut new_doc, &options, &mut Vec::new()).unwrap();
+            assert_eq!(new_doc.get_str(field).unwrap(), expected, "case {field}");
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use std::time::Instant;
+
+use futures::TryStreamExt;
+use mongodb::bson;
+
+use crate::{fix_document, CollectionReport, RepairDiff, RepairError, RepairOptions};
+
+/// Hex `_id` of an object id, or the extended JSON of any other `_id`.
+fn display_id(id: &bson::Bson) -> String {
+    match id {
+        bson::Bson::ObjectId(id) => id.to_hex(),
+        other => other.clone().into_relaxed_extjson().to_string(),
+    }
+}
+
+/// Repair the inserts and updates of `collection` recorded in the oplog
+/// (`local.oplog.rs`) and re-apply the repaired operations, in oplog order.
+///
+/// Inserts are re-applied as upserting replacements of the inserted document,
+/// `$set` style updates and replacements as they were recorded. Updates in the
+/// `$v: 2` delta format cannot be re-applied and are skipped.
+///
+/// Requires a replica set member and the privileges to read the `local` database.
+pub async fn fix_oplog(
+    client: &mongodb::Client,
+    database: &str,
+    collection: &str,
+    options: &RepairOptions,
+) -> crate::Result<CollectionReport> {
+    let started = Instant::now();
+    let mut report = CollectionReport::new(collection);
+    let target = client
+        .database(database)
+        .collection::<bson::Document>(collection);
+    let mut entries = client
+        .database("local")
+        .collection::<bson::RawDocumentBuf>("oplog.rs")
+        .find(bson::doc! {
+            "ns": format!("{database}.{collection}"),
+            "op": { "$in": ["i", "u"] },
+        })
+        .await?;
+
+    while let Some(entry) = entries.try_next().await? {
+        report.metrics.total_docs += 1;
+        let op = entry.get_str("op")?;
+        let o = entry.get_document("o")?;
+        let filter = if op == "i" {
+            let id = o
+                .get("_id")?
+                .ok_or_else(|| RepairError::Other("oplog insert without _id".to_string()))?;
+            bson::doc! {"_id": bson::Bson::try_from(id.to_raw_bson())?}
+        } else {
+            bson::Document::try_from(entry.get_document("o2")?)?
+        };
+        let id = filter.get("_id").map(display_id).unwrap_or_default();
+
+        let mut new_o = bson::RawDocumentBuf::new();
+        let changes = match fix_document(o, &mut new_o, options, &mut Vec::new()) {
+            Ok(changes) => changes,
+            Err(err) => {
+                eprintln!(
+                    "collection = {: <20} id = {: <30} FAILED {err}",
+                    collection, id
+                );
+                report.metrics.error_docs += 1;
+                continue;
+            }
+        };
+        if changes.is_empty() {
+            continue;
+        }
+        let new_o = new_o.to_document()?;
+        if op == "u" && new_o.contains_key("$v") && new_o.contains_key("diff") {
+            eprintln!(
+                "collection = {: <20} id = {: <30} SKIPPED (delta updates cannot be re-applied)",
+                collection, id
+            );
+            report.metrics.skipped_docs += 1;
+            continue;
+        }
+        report.metrics.changed_docs += 1;
+        report.metrics.total_fields_fixed += changes.len() as u64;
+        report.changes.push(RepairDiff {
+            collection: collection.to_string(),
+            id: id.clone(),
+            field_changes: changes,
+        });
+        if options.dry_run {
+            continue;
+        }
+
+        let is_update = new_o.keys().next().is_some_and(|key| key.starts_with('$'));
+        if is_update {
+            target.update_one(filter, new_o).await?;
+        } else {
+            target.replace_one(filter, new_o).upsert(op == "i").await?;
+        }
+        println!(
+            "collection = {: <20} id = {: <30} REPLAYED ({})",
+            collection,
+            id,
+            if op == "i" { "insert" } else { "update" }
+        );
+    }
+    report.metrics.elapsed = started.elapsed();
+    Ok(report)
+}
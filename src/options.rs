@@ -0,0 +1,917 @@
+use std::sync::Arc;
+
+use mongodb::bson;
+
+#[cfg(debug_assertions)]
+use crate::SimulatedWriteFailure;
+use crate::{
+    AlwaysConfirm, AuditFile, CollectionOrder, ConfirmationStrategy, ConstraintAction,
+    CustomEncoding, DedupAction, DocumentExport, Encoding, FieldBlocklist, FieldStats,
+    InteractiveConfirm, JqFilter, LegacyUuidStyle, RepairedValues, ReportStream, SplitStrategy,
+    TransformScript,
+};
+
+/// Maximum BSON document size accepted by MongoDB (16 MiB).
+pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Options controlling how documents are repaired.
+///
+/// Construct with [`RepairOptions::default`] and chain the setters:
+///
+/// ```
+/// use mongo_repair_utf8::{Encoding, RepairOptions};
+///
+/// let options = RepairOptions::default()
+///     .encoding(Encoding::Utf16)
+///     .dry_run(true)
+///     .concurrency(4)
+///     .build();
+/// assert!(options.dry_run);
+/// assert_eq!(options.concurrency, 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RepairOptions {
+    /// Encoding used to reinterpret invalid UTF-8 string values.
+    pub encoding: Encoding,
+    /// Decides whether each repaired field is changed.
+    pub confirmation: Arc<dyn ConfirmationStrategy>,
+    /// Compute repairs without writing them back.
+    pub dry_run: bool,
+    /// Number of collections repaired concurrently.
+    pub concurrency: usize,
+    /// Re-read each document right before replacing it and skip the write
+    /// if another writer changed it in the meantime.
+    pub verify_before_repair: bool,
+    /// JSON Schema that repaired documents must satisfy before they are written.
+    pub schema: Option<Arc<jsonschema::Validator>>,
+    /// Check repaired documents against the collection's `$jsonSchema` validator
+    /// and fail before writing a document that would violate it.
+    pub collection_validator_check: bool,
+    /// Field set to the current time on every changed document.
+    pub update_timestamp_field: Option<String>,
+    /// Value of the `updatedBy` field set on every changed document.
+    pub updated_by: Option<String>,
+    /// Also repair field names that are not valid UTF-8.
+    ///
+    /// Repaired field names are only reported, unless [`RepairOptions::rename_repaired_keys`] is set.
+    pub repair_keys: bool,
+    /// Rename fields to their names repaired by [`RepairOptions::repair_keys`],
+    /// unless a field with the repaired name exists.
+    pub rename_repaired_keys: bool,
+    /// Size in bytes a repaired document may not exceed.
+    pub max_document_size: usize,
+    /// Fail instead of skipping documents that exceed [`RepairOptions::max_document_size`].
+    pub strict_size: bool,
+    /// How to shrink repaired documents that exceed [`RepairOptions::max_document_size`].
+    /// Without a strategy they are skipped (or fail with [`RepairOptions::strict_size`]).
+    pub split_strategy: Option<SplitStrategy>,
+    /// Only print documents with repaired values (errors are still printed).
+    pub quiet: bool,
+    /// Print a progress line every this many documents when stderr is not a terminal.
+    /// Zero disables progress reporting.
+    pub progress_interval: u64,
+    /// Stop repairing a collection after this many documents failed.
+    /// Zero means no limit.
+    pub max_errors_per_collection: u64,
+    /// Skip repaired documents with fewer changed fields than this,
+    /// to avoid rewriting large documents for a single minor repair.
+    pub min_changed_fields: usize,
+    /// Number of documents of a collection repaired and written concurrently.
+    /// Higher values hide the write latency of a remote server, but increase
+    /// contention on the collection, and documents are no longer processed in cursor order.
+    pub parallel_documents: usize,
+    /// Make every Nth document replacement of each collection fail with a
+    /// synthetic error, to exercise error handling. Only available in debug builds.
+    #[cfg(debug_assertions)]
+    pub simulate_write_failure: Option<Arc<SimulatedWriteFailure>>,
+    /// Prepare replacements as usual, but yield instead of sending them,
+    /// to profile the cost of the repair without network I/O.
+    pub noop_writes: bool,
+    /// Top-level shard key field, copied from the original document into the
+    /// replacement filter to target a single shard.
+    pub shard_key: Option<String>,
+    /// Only read these top-level fields of each document, merging the repaired
+    /// fields into the full document (read in a second query) before it is replaced.
+    pub projection: Option<bson::Document>,
+    /// Index hint for the query reading the documents of a collection.
+    pub hint: Option<bson::Document>,
+    /// Server-side time limit of the query reading the documents of a collection.
+    pub cursor_max_time: Option<std::time::Duration>,
+    /// Only repair values whose lossy UTF-8 rendering matches this pattern.
+    pub repair_regex: Option<regex::Regex>,
+    /// Skip documents whose hex `_id` matches this pattern.
+    pub skip_regex: Option<regex::Regex>,
+    /// String-like BSON types whose values are repaired (string, symbol and JavaScript code).
+    pub field_types: Vec<bson::spec::ElementType>,
+    /// Number of unchanged lines shown around changes in document diffs.
+    pub diff_context_lines: usize,
+    /// Leave values unrepaired if the repaired value is longer than this multiple
+    /// of the original byte length, which hints at the wrong encoding.
+    pub max_replacement_ratio: Option<f64>,
+    /// Leave values unrepaired if the [`byte_entropy`](crate::byte_entropy) of their
+    /// raw bytes exceeds this many bits per byte, which hints at binary data.
+    ///
+    /// The default of 8 is the maximum entropy, so that every value is repaired.
+    pub field_entropy_threshold: f64,
+    /// Leave values unrepaired if more than this fraction of their raw bytes
+    /// are ASCII, so that they are unlikely to be in a double-byte encoding.
+    ///
+    /// The default of 1 never skips a value, as Latin-1 text like `caf\xe9` is
+    /// mostly ASCII but still broken.
+    pub field_ascii_ratio_threshold: f64,
+    /// Decode each value with the encoding producing the fewest replacement
+    /// characters, preferring [`RepairOptions::encoding`] on ties.
+    pub split_encoding: bool,
+    /// Print additional details of each repair, such as the chosen encoding.
+    pub verbose: bool,
+    /// Stop the whole run at the first document or collection that fails,
+    /// instead of logging the error and continuing.
+    pub abort_on_error: bool,
+    /// Copy fields whose values cannot be parsed (for reasons other than invalid
+    /// UTF-8) verbatim instead of failing the document.
+    pub ignore_fields_with_errors: bool,
+    /// Do not print documents without repaired values, unless [`RepairOptions::verbose`] is set.
+    pub skip_unchanged: bool,
+    /// Write the original of every document with repaired values to this export.
+    /// Documents are exported instead of being replaced.
+    pub export_broken: Option<Arc<DocumentExport>>,
+    /// Report generic binary values whose bytes are text in [`RepairOptions::encoding`].
+    pub include_binary_fields: bool,
+    /// Convert legacy UUIDs (binary subtype 3) written in this byte order to standard UUIDs.
+    pub normalize_legacy_uuid: Option<LegacyUuidStyle>,
+    /// Convert the binary values found with [`RepairOptions::include_binary_fields`] to strings.
+    pub fix_binary_fields: bool,
+    /// Write every document, repaired where needed, to this export instead of
+    /// replacing the changed documents.
+    pub output_bson: Option<Arc<DocumentExport>>,
+    /// Only write documents with repaired values to [`RepairOptions::output_bson`].
+    pub only_changed: bool,
+    /// Read replaced documents back and check that they are valid UTF-8.
+    pub validate_post_repair: bool,
+    /// Number of documents read back per query by [`RepairOptions::validate_post_repair`],
+    /// and read per query by the second pass of [`RepairOptions::two_pass_scan`].
+    pub bulk_find_batch: usize,
+    /// Version field matched in the replacement filter and incremented in the
+    /// replacement, for optimistic concurrency control.
+    pub version_field: Option<String>,
+    /// How often a document is read and repaired again after its
+    /// [`RepairOptions::version_field`] changed concurrently.
+    pub max_retries: u32,
+    /// Pause writing while a secondary lags further than this behind the primary,
+    /// until the lag dropped below half of it.
+    pub replication_lag_tolerance: Option<std::time::Duration>,
+    /// Fields moved from the first to the second dot-notation path in repaired documents.
+    pub rename_fields: Vec<(String, String)>,
+    /// Table of [`Encoding::Custom`].
+    pub custom_encoding: Option<Arc<CustomEncoding>>,
+    /// Script of [`Encoding::Script`].
+    pub transform_script: Option<Arc<TransformScript>>,
+    /// Minimum length in characters of the repaired values of dot-notation paths.
+    pub field_min_lengths: Vec<(String, usize)>,
+    /// Maximum length in characters of the repaired values of dot-notation paths.
+    pub field_max_lengths: Vec<(String, usize)>,
+    /// What to do with repaired values violating the length constraints.
+    pub constraint_violation_action: ConstraintAction,
+    /// Only count the repairs, without printing documents, diffs or repaired values.
+    pub count_only: bool,
+    /// Skip collections whose names sort before this one.
+    pub start_from_collection: Option<String>,
+    /// Skip collections whose names sort after this one.
+    pub stop_after_collection: Option<String>,
+    /// Read the documents of a collection in random order (with `$sample`),
+    /// to spread the writes over the shards.
+    pub randomize_order: bool,
+    /// Scan each collection for broken documents first, then read only those
+    /// by `_id` to repair them, which is faster for sparsely corrupt collections.
+    pub two_pass_scan: bool,
+    /// Database (possibly of another cluster) that all documents are upserted into,
+    /// instead of replacing the repaired documents in place.
+    pub destination: Option<mongodb::Database>,
+    /// Collection of [`RepairOptions::destination`] written to, instead of the
+    /// collection of the same name.
+    pub destination_collection: Option<String>,
+    /// Per-field statistics of the decoded string values.
+    pub field_stats: Option<Arc<FieldStats>>,
+    /// Print latency percentiles of the stages of processing each collection.
+    pub timing_profile: bool,
+    /// Include the hex encoding of the original bytes of repaired values in the changes.
+    pub report_raw_bytes: bool,
+    /// Bytes of a value included by [`RepairOptions::report_raw_bytes`].
+    pub max_hex_bytes: usize,
+    /// Time budget of a single collection, after which it is abandoned.
+    pub collection_timeout: Option<std::time::Duration>,
+    /// Order of the collections repaired by a [`RepairSession`](crate::RepairSession).
+    pub collection_order: CollectionOrder,
+    /// Collections repaired before all others, in this order.
+    pub prioritize_collections: Vec<String>,
+    /// Shell command run before each collection of a [`RepairSession`](crate::RepairSession),
+    /// e.g. to quiesce the application, with the collection as `$MONGO_COLLECTION`.
+    ///
+    /// The collection is skipped if the command fails.
+    pub pre_repair_hook: Option<String>,
+    /// Shell command run after each collection of a [`RepairSession`](crate::RepairSession),
+    /// with the collection as `$MONGO_COLLECTION`.
+    pub post_repair_hook: Option<String>,
+    /// Also repair the string values of JSON objects and arrays serialized in repaired strings.
+    pub repair_embedded_json: bool,
+    /// Collapse whitespace in repaired strings into single spaces and trim them.
+    pub normalize_whitespace: bool,
+    /// Keep the lossy UTF-8 decoding of values whose repair contains more
+    /// replacement characters (`U+FFFD`) than it.
+    pub graceful_degradation: bool,
+    /// Fail documents with values whose bytes contain overlong encodings or encoded
+    /// UTF-16 surrogates, or whose decoding replaced invalid sequences.
+    pub validate_utf8_strictly: bool,
+    /// Repair every repaired document again and log an error if that changes it.
+    pub test_idempotency: bool,
+    /// Fields copied verbatim instead of being repaired.
+    pub field_blocklist: Option<Arc<FieldBlocklist>>,
+    /// Warn about collections in which a smaller fraction of the documents changed.
+    pub min_changed_ratio: Option<f64>,
+    /// Aggregation stages that select (and transform) the documents to repair.
+    ///
+    /// Repaired documents replace the document with the same `_id`.
+    pub pipeline: Vec<bson::Document>,
+    /// Only repair documents selected by this jq expression.
+    pub jq_filter: Option<Arc<JqFilter>>,
+    /// Collection the changes of every repaired document are inserted into.
+    pub audit_collection: Option<mongodb::Collection<bson::Document>>,
+    /// JSON Lines file the changes of every repaired document are appended to.
+    pub audit_file: Option<Arc<AuditFile>>,
+    /// Read concern of the cursor reading the documents of a collection.
+    ///
+    /// With [`ReadConcern::snapshot`](mongodb::options::ReadConcern::snapshot), the reads of
+    /// a collection run in a snapshot session and see a consistent point in time, even
+    /// while other clients write. Snapshot reads require a replica set or sharded cluster
+    /// (MongoDB 5.0+), keep old versions of documents in the server's cache for as long as
+    /// the cursor is open, and fail once the cursor outlives the server's
+    /// `minSnapshotHistoryWindowInSeconds`.
+    pub read_concern: Option<mongodb::options::ReadConcern>,
+    /// Live report of progress (every [`RepairOptions::progress_interval`] documents),
+    /// changed documents and finished collections.
+    pub report_stream: Option<Arc<ReportStream>>,
+    /// Repaired values of a field that must stay unique within a collection.
+    pub deduplicate_repaired: Option<Arc<RepairedValues>>,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::default(),
+            confirmation: Arc::new(AlwaysConfirm),
+            dry_run: false,
+            concurrency: 1,
+            verify_before_repair: false,
+            schema: None,
+            collection_validator_check: false,
+            update_timestamp_field: None,
+            updated_by: None,
+            repair_keys: false,
+            rename_repaired_keys: false,
+            max_document_size: MAX_DOCUMENT_SIZE,
+            strict_size: false,
+            split_strategy: None,
+            quiet: false,
+            progress_interval: 1000,
+            max_errors_per_collection: 0,
+            min_changed_fields: 1,
+            parallel_documents: 1,
+            #[cfg(debug_assertions)]
+            simulate_write_failure: None,
+            noop_writes: false,
+            shard_key: None,
+            projection: None,
+            hint: None,
+            cursor_max_time: None,
+            repair_regex: None,
+            skip_regex: None,
+            field_types: vec![bson::spec::ElementType::String],
+            diff_context_lines: 3,
+            max_replacement_ratio: None,
+            field_entropy_threshold: 8.0,
+            field_ascii_ratio_threshold: 1.0,
+            split_encoding: false,
+            verbose: false,
+            abort_on_error: false,
+            ignore_fields_with_errors: false,
+            skip_unchanged: false,
+            export_broken: None,
+            include_binary_fields: false,
+            normalize_legacy_uuid: None,
+            fix_binary_fields: false,
+            output_bson: None,
+            only_changed: false,
+            validate_post_repair: false,
+            bulk_find_batch: 100,
+            version_field: None,
+            max_retries: 3,
+            replication_lag_tolerance: None,
+            rename_fields: Vec::new(),
+            custom_encoding: None,
+            transform_script: None,
+            field_min_lengths: Vec::new(),
+            field_max_lengths: Vec::new(),
+            constraint_violation_action: ConstraintAction::default(),
+            count_only: false,
+            start_from_collection: None,
+            stop_after_collection: None,
+            randomize_order: false,
+            two_pass_scan: false,
+            destination: None,
+            destination_collection: None,
+            field_stats: None,
+            timing_profile: false,
+            report_raw_bytes: false,
+            max_hex_bytes: 256,
+            collection_timeout: None,
+            collection_order: CollectionOrder::default(),
+            prioritize_collections: Vec::new(),
+            pre_repair_hook: None,
+            post_repair_hook: None,
+            repair_embedded_json: false,
+            normalize_whitespace: false,
+            graceful_degradation: false,
+            validate_utf8_strictly: false,
+            test_idempotency: false,
+            field_blocklist: None,
+            min_changed_ratio: None,
+            pipeline: Vec::new(),
+            jq_filter: None,
+            audit_collection: None,
+            audit_file: None,
+            read_concern: None,
+            report_stream: None,
+            deduplicate_repaired: None,
+        }
+    }
+}
+
+impl RepairOptions {
+    #[must_use]
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Ask interactively before each field is changed, instead of changing all fields.
+    #[must_use]
+    pub fn confirm(self, confirm: bool) -> Self {
+        if confirm {
+            self.confirmation(InteractiveConfirm::default())
+        } else {
+            self.confirmation(AlwaysConfirm)
+        }
+    }
+
+    #[must_use]
+    pub fn confirmation(mut self, confirmation: impl ConfirmationStrategy + 'static) -> Self {
+        self.confirmation = Arc::new(confirmation);
+        self
+    }
+
+    #[must_use]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    #[must_use]
+    pub fn verify_before_repair(mut self, verify_before_repair: bool) -> Self {
+        self.verify_before_repair = verify_before_repair;
+        self
+    }
+
+    #[must_use]
+    pub fn schema(mut self, schema: jsonschema::Validator) -> Self {
+        self.schema = Some(Arc::new(schema));
+        self
+    }
+
+    #[must_use]
+    pub fn collection_validator_check(mut self, collection_validator_check: bool) -> Self {
+        self.collection_validator_check = collection_validator_check;
+        self
+    }
+
+    #[must_use]
+    pub fn update_timestamp_field(mut self, update_timestamp_field: impl Into<String>) -> Self {
+        self.update_timestamp_field = Some(update_timestamp_field.into());
+        self
+    }
+
+    #[must_use]
+    pub fn updated_by(mut self, updated_by: impl Into<String>) -> Self {
+        self.updated_by = Some(updated_by.into());
+        self
+    }
+
+    #[must_use]
+    pub fn repair_keys(mut self, repair_keys: bool) -> Self {
+        self.repair_keys = repair_keys;
+        self
+    }
+
+    #[must_use]
+    pub fn rename_repaired_keys(mut self, rename_repaired_keys: bool) -> Self {
+        self.rename_repaired_keys = rename_repaired_keys;
+        self
+    }
+
+    #[must_use]
+    pub fn max_document_size(mut self, max_document_size: usize) -> Self {
+        self.max_document_size = max_document_size;
+        self
+    }
+
+    #[must_use]
+    pub fn strict_size(mut self, strict_size: bool) -> Self {
+        self.strict_size = strict_size;
+        self
+    }
+
+    #[must_use]
+    pub fn split_strategy(mut self, split_strategy: SplitStrategy) -> Self {
+        self.split_strategy = Some(split_strategy);
+        self
+    }
+
+    #[must_use]
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    #[must_use]
+    pub fn progress_interval(mut self, progress_interval: u64) -> Self {
+        self.progress_interval = progress_interval;
+        self
+    }
+
+    #[must_use]
+    pub fn max_errors_per_collection(mut self, max_errors_per_collection: u64) -> Self {
+        self.max_errors_per_collection = max_errors_per_collection;
+        self
+    }
+
+    #[must_use]
+    pub fn min_changed_fields(mut self, min_changed_fields: usize) -> Self {
+        self.min_changed_fields = min_changed_fields;
+        self
+    }
+
+    #[must_use]
+    pub fn parallel_documents(mut self, parallel_documents: usize) -> Self {
+        self.parallel_documents = parallel_documents;
+        self
+    }
+
+    #[cfg(debug_assertions)]
+    #[must_use]
+    pub fn simulate_write_failure(mut self, every: u64) -> Self {
+        self.simulate_write_failure = Some(Arc::new(SimulatedWriteFailure::new(every)));
+        self
+    }
+
+    #[must_use]
+    pub fn noop_writes(mut self, noop_writes: bool) -> Self {
+        self.noop_writes = noop_writes;
+        self
+    }
+
+    #[must_use]
+    pub fn shard_key(mut self, shard_key: impl Into<String>) -> Self {
+        self.shard_key = Some(shard_key.into());
+        self
+    }
+
+    #[must_use]
+    pub fn projection(mut self, projection: bson::Document) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    #[must_use]
+    pub fn hint(mut self, hint: bson::Document) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    #[must_use]
+    pub fn cursor_max_time(mut self, cursor_max_time: std::time::Duration) -> Self {
+        self.cursor_max_time = Some(cursor_max_time);
+        self
+    }
+
+    #[must_use]
+    pub fn repair_regex(mut self, repair_regex: regex::Regex) -> Self {
+        self.repair_regex = Some(repair_regex);
+        self
+    }
+
+    #[must_use]
+    pub fn skip_regex(mut self, skip_regex: regex::Regex) -> Self {
+        self.skip_regex = Some(skip_regex);
+        self
+    }
+
+    #[must_use]
+    pub fn field_types(mut self, field_types: Vec<bson::spec::ElementType>) -> Self {
+        self.field_types = field_types;
+        self
+    }
+
+    #[must_use]
+    pub fn diff_context_lines(mut self, diff_context_lines: usize) -> Self {
+        self.diff_context_lines = diff_context_lines;
+        self
+    }
+
+    #[must_use]
+    pub fn max_replacement_ratio(mut self, max_replacement_ratio: f64) -> Self {
+        self.max_replacement_ratio = Some(max_replacement_ratio);
+        self
+    }
+
+    #[must_use]
+    pub fn field_entropy_threshold(mut self, field_entropy_threshold: f64) -> Self {
+        self.field_entropy_threshold = field_entropy_threshold;
+        self
+    }
+
+    #[must_use]
+    pub fn field_ascii_ratio_threshold(mut self, field_ascii_ratio_threshold: f64) -> Self {
+        self.field_ascii_ratio_threshold = field_ascii_ratio_threshold;
+        self
+    }
+
+    #[must_use]
+    pub fn split_encoding(mut self, split_encoding: bool) -> Self {
+        self.split_encoding = split_encoding;
+        self
+    }
+
+    #[must_use]
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    #[must_use]
+    pub fn abort_on_error(mut self, abort_on_error: bool) -> Self {
+        self.abort_on_error = abort_on_error;
+        self
+    }
+
+    #[must_use]
+    pub fn ignore_fields_with_errors(mut self, ignore_fields_with_errors: bool) -> Self {
+        self.ignore_fields_with_errors = ignore_fields_with_errors;
+        self
+    }
+
+    #[must_use]
+    pub fn skip_unchanged(mut self, skip_unchanged: bool) -> Self {
+        self.skip_unchanged = skip_unchanged;
+        self
+    }
+
+    #[must_use]
+    pub fn export_broken(mut self, export_broken: DocumentExport) -> Self {
+        self.export_broken = Some(Arc::new(export_broken));
+        self
+    }
+
+    #[must_use]
+    pub fn include_binary_fields(mut self, include_binary_fields: bool) -> Self {
+        self.include_binary_fields = include_binary_fields;
+        self
+    }
+
+    #[must_use]
+    pub fn normalize_legacy_uuid(mut self, style: LegacyUuidStyle) -> Self {
+        self.normalize_legacy_uuid = Some(style);
+        self
+    }
+
+    #[must_use]
+    pub fn fix_binary_fields(mut self, fix_binary_fields: bool) -> Self {
+        self.fix_binary_fields = fix_binary_fields;
+        self
+    }
+
+    #[must_use]
+    pub fn output_bson(mut self, output_bson: DocumentExport) -> Self {
+        self.output_bson = Some(Arc::new(output_bson));
+        self
+    }
+
+    #[must_use]
+    pub fn only_changed(mut self, only_changed: bool) -> Self {
+        self.only_changed = only_changed;
+        self
+    }
+
+    #[must_use]
+    pub fn validate_post_repair(mut self, validate_post_repair: bool) -> Self {
+        self.validate_post_repair = validate_post_repair;
+        self
+    }
+
+    #[must_use]
+    pub fn bulk_find_batch(mut self, bulk_find_batch: usize) -> Self {
+        self.bulk_find_batch = bulk_find_batch;
+        self
+    }
+
+    #[must_use]
+    pub fn version_field(mut self, version_field: impl Into<String>) -> Self {
+        self.version_field = Some(version_field.into());
+        self
+    }
+
+    #[must_use]
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    #[must_use]
+    pub fn replication_lag_tolerance(
+        mut self,
+        replication_lag_tolerance: std::time::Duration,
+    ) -> Self {
+        self.replication_lag_tolerance = Some(replication_lag_tolerance);
+        self
+    }
+
+    #[must_use]
+    pub fn rename_field(
+        mut self,
+        old_path: impl Into<String>,
+        new_path: impl Into<String>,
+    ) -> Self {
+        self.rename_fields.push((old_path.into(), new_path.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn transform_script(mut self, transform_script: TransformScript) -> Self {
+        self.transform_script = Some(Arc::new(transform_script));
+        self
+    }
+
+    #[must_use]
+    pub fn custom_encoding(mut self, custom_encoding: CustomEncoding) -> Self {
+        self.custom_encoding = Some(Arc::new(custom_encoding));
+        self
+    }
+
+    #[must_use]
+    pub fn field_min_length(mut self, path: impl Into<String>, min: usize) -> Self {
+        self.field_min_lengths.push((path.into(), min));
+        self
+    }
+
+    #[must_use]
+    pub fn field_max_length(mut self, path: impl Into<String>, max: usize) -> Self {
+        self.field_max_lengths.push((path.into(), max));
+        self
+    }
+
+    #[must_use]
+    pub fn constraint_violation_action(mut self, action: ConstraintAction) -> Self {
+        self.constraint_violation_action = action;
+        self
+    }
+
+    #[must_use]
+    pub fn count_only(mut self, count_only: bool) -> Self {
+        self.count_only = count_only;
+        self
+    }
+
+    #[must_use]
+    pub fn start_from_collection(mut self, start_from_collection: impl Into<String>) -> Self {
+        self.start_from_collection = Some(start_from_collection.into());
+        self
+    }
+
+    #[must_use]
+    pub fn stop_after_collection(mut self, stop_after_collection: impl Into<String>) -> Self {
+        self.stop_after_collection = Some(stop_after_collection.into());
+        self
+    }
+
+    #[must_use]
+    pub fn randomize_order(mut self, randomize_order: bool) -> Self {
+        self.randomize_order = randomize_order;
+        self
+    }
+
+    #[must_use]
+    pub fn two_pass_scan(mut self, two_pass_scan: bool) -> Self {
+        self.two_pass_scan = two_pass_scan;
+        self
+    }
+
+    #[must_use]
+    pub fn destination(mut self, destination: mongodb::Database) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    #[must_use]
+    pub fn destination_collection(mut self, destination_collection: impl Into<String>) -> Self {
+        self.destination_collection = Some(destination_collection.into());
+        self
+    }
+
+    #[must_use]
+    pub fn field_stats(mut self, field_stats: FieldStats) -> Self {
+        self.field_stats = Some(Arc::new(field_stats));
+        self
+    }
+
+    #[must_use]
+    pub fn timing_profile(mut self, timing_profile: bool) -> Self {
+        self.timing_profile = timing_profile;
+        self
+    }
+
+    #[must_use]
+    pub fn report_raw_bytes(mut self, report_raw_bytes: bool) -> Self {
+        self.report_raw_bytes = report_raw_bytes;
+        self
+    }
+
+    #[must_use]
+    pub fn max_hex_bytes(mut self, max_hex_bytes: usize) -> Self {
+        self.max_hex_bytes = max_hex_bytes;
+        self
+    }
+
+    #[must_use]
+    pub fn collection_timeout(mut self, collection_timeout: std::time::Duration) -> Self {
+        self.collection_timeout = Some(collection_timeout);
+        self
+    }
+
+    #[must_use]
+    pub fn collection_order(mut self, collection_order: CollectionOrder) -> Self {
+        self.collection_order = collection_order;
+        self
+    }
+
+    #[must_use]
+    pub fn prioritize_collections(mut self, prioritize_collections: Vec<String>) -> Self {
+        self.prioritize_collections = prioritize_collections;
+        self
+    }
+
+    #[must_use]
+    pub fn pre_repair_hook(mut self, pre_repair_hook: impl Into<String>) -> Self {
+        self.pre_repair_hook = Some(pre_repair_hook.into());
+        self
+    }
+
+    #[must_use]
+    pub fn post_repair_hook(mut self, post_repair_hook: impl Into<String>) -> Self {
+        self.post_repair_hook = Some(post_repair_hook.into());
+        self
+    }
+
+    #[must_use]
+    pub fn repair_embedded_json(mut self, repair_embedded_json: bool) -> Self {
+        self.repair_embedded_json = repair_embedded_json;
+        self
+    }
+
+    #[must_use]
+    pub fn normalize_whitespace(mut self, normalize_whitespace: bool) -> Self {
+        self.normalize_whitespace = normalize_whitespace;
+        self
+    }
+
+    #[must_use]
+    pub fn graceful_degradation(mut self, graceful_degradation: bool) -> Self {
+        self.graceful_degradation = graceful_degradation;
+        self
+    }
+
+    #[must_use]
+    pub fn validate_utf8_strictly(mut self, validate_utf8_strictly: bool) -> Self {
+        self.validate_utf8_strictly = validate_utf8_strictly;
+        self
+    }
+
+    #[must_use]
+    pub fn test_idempotency(mut self, test_idempotency: bool) -> Self {
+        self.test_idempotency = test_idempotency;
+        self
+    }
+
+    #[must_use]
+    pub fn field_blocklist(mut self, field_blocklist: FieldBlocklist) -> Self {
+        self.field_blocklist = Some(Arc::new(field_blocklist));
+        self
+    }
+
+    #[must_use]
+    pub fn min_changed_ratio(mut self, min_changed_ratio: f64) -> Self {
+        self.min_changed_ratio = Some(min_changed_ratio);
+        self
+    }
+
+    #[must_use]
+    pub fn pipeline(mut self, pipeline: Vec<bson::Document>) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    #[must_use]
+    pub fn jq_filter(mut self, jq_filter: JqFilter) -> Self {
+        self.jq_filter = Some(Arc::new(jq_filter));
+        self
+    }
+
+    #[must_use]
+    pub fn audit_collection(
+        mut self,
+        audit_collection: mongodb::Collection<bson::Document>,
+    ) -> Self {
+        self.audit_collection = Some(audit_collection);
+        self
+    }
+
+    #[must_use]
+    pub fn audit_file(mut self, audit_file: AuditFile) -> Self {
+        self.audit_file = Some(Arc::new(audit_file));
+        self
+    }
+
+    #[must_use]
+    pub fn read_concern(mut self, read_concern: mongodb::options::ReadConcern) -> Self {
+        self.read_concern = Some(read_concern);
+        self
+    }
+
+    #[must_use]
+    pub fn report_stream(mut self, report_stream: ReportStream) -> Self {
+        self.report_stream = Some(Arc::new(report_stream));
+        self
+    }
+
+    /// Detect repaired values of the dot-notation `field` that collide with the
+    /// repaired value of another document, handling them with `action`.
+    #[must_use]
+    pub fn deduplicate_repaired(mut self, field: impl Into<String>, action: DedupAction) -> Self {
+        self.deduplicate_repaired = Some(Arc::new(RepairedValues::new(field, action)));
+        self
+    }
+
+    /// Decode `raw` with the [`RepairOptions::encoding`], using the
+    /// [`RepairOptions::custom_encoding`] for [`Encoding::Custom`].
+    pub(crate) fn decode(&self, raw: &[u8]) -> String {
+        match (self.encoding, &self.custom_encoding) {
+            (Encoding::Custom, Some(custom_encoding)) => custom_encoding.decode(raw),
+            (encoding, _) => encoding.decode(raw),
+        }
+    }
+
+    /// Whether repaired documents are replaced in the collection, rather than
+    /// only reported or written to a file or destination.
+    #[must_use]
+    pub fn writes_documents(&self) -> bool {
+        !self.dry_run
+            && !self.noop_writes
+            && self.export_broken.is_none()
+            && self.output_bson.is_none()
+            && self.destination.is_none()
+    }
+
+    /// Flush the documents buffered by [`RepairOptions::export_broken`] and
+    /// [`RepairOptions::output_bson`].
+    pub fn flush_exports(&self) -> crate::Result<()> {
+        for export in [&self.export_broken, &self.output_bson]
+            .into_iter()
+            .flatten()
+        {
+            export.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Finish building, normalizing out-of-range values.
+    #[must_use]
+    pub fn build(mut self) -> Self {
+        self.concurrency = self.concurrency.max(1);
+        self.parallel_documents = self.parallel_documents.max(1);
+        self.bulk_find_batch = self.bulk_find_batch.max(1);
+        self
+    }
+}
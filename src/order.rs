@@ -0,0 +1,102 @@
+use std::fmt;
+use std::str::FromStr;
+
+use mongodb::bson;
+
+/// Order in which a [`RepairSession`](crate::RepairSession) repairs the collections of a database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CollectionOrder {
+    /// As listed by the server, or as given.
+    #[default]
+    Natural,
+    Alphabetical,
+    /// Reverse alphabetical.
+    Reverse,
+    /// Smallest `storageSize` first, for faster feedback on whether the repair works.
+    SizeAsc,
+    /// Largest `storageSize` first.
+    SizeDesc,
+}
+
+impl CollectionOrder {
+    /// All supported orders.
+    pub const ALL: [CollectionOrder; 5] = [
+        CollectionOrder::Natural,
+        CollectionOrder::Alphabetical,
+        CollectionOrder::Reverse,
+        CollectionOrder::SizeAsc,
+        CollectionOrder::SizeDesc,
+    ];
+
+    /// Human-readable name, as accepted by [`FromStr`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Natural => "natural",
+            Self::Alphabetical => "alphabetical",
+            Self::Reverse => "reverse",
+            Self::SizeAsc => "size-asc",
+            Self::SizeDesc => "size-desc",
+        }
+    }
+
+    /// Sort `collection_names` of `db`, querying `collStats` for the sizes.
+    pub(crate) async fn sort(
+        self,
+        db: &mongodb::Database,
+        collection_names: &mut Vec<String>,
+    ) -> crate::Result<()> {
+        match self {
+            Self::Natural => {}
+            Self::Alphabetical => collection_names.sort(),
+            Self::Reverse => collection_names.sort_by(|a, b| b.cmp(a)),
+            Self::SizeAsc | Self::SizeDesc => {
+                let mut sized = Vec::with_capacity(collection_names.len());
+                for name in collection_names.drain(..) {
+                    sized.push((storage_size(db, &name).await?, name));
+                }
+                sized.sort();
+                if self == Self::SizeDesc {
+                    sized.reverse();
+                }
+                collection_names.extend(sized.into_iter().map(|(_, name)| name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Storage size in bytes of collection `name` of `db`, as reported by `collStats`.
+async fn storage_size(db: &mongodb::Database, name: &str) -> crate::Result<i64> {
+    let stats = db.run_command(bson::doc! { "collStats": name }).await?;
+    // the server picks the smallest numeric type that fits
+    Ok(match stats.get("storageSize") {
+        Some(bson::Bson::Int32(size)) => i64::from(*size),
+        Some(bson::Bson::Int64(size)) => *size,
+        Some(bson::Bson::Double(size)) => *size as i64,
+        _ => 0,
+    })
+}
+
+impl fmt::Display for CollectionOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CollectionOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|order| order.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|order| order.name()).collect();
+                format!(
+                    "unknown collection order {s:?} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
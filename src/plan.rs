@@ -0,0 +1,120 @@
+use std::fmt;
+
+use mongodb::bson;
+
+use crate::changes::update_document;
+use crate::{fix_collection, AlwaysConfirm, RepairDiff, RepairError, RepairOptions};
+
+/// Maximum number of updates sent in one `update` command, well below the
+/// server's `maxWriteBatchSize`.
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// All changes of a collection, computed by a dry run before any is applied.
+///
+/// Unlike [`fix_collection`], which writes each document as soon as it is
+/// repaired, a plan is shown (or saved) in full, confirmed once and then
+/// applied with bulk `update` commands:
+///
+/// ```no_run
+/// use mongo_repair_utf8::{BatchRepairPlan, RepairOptions};
+/// use mongodb::{bson, Client};
+///
+/// # async fn run() -> color_eyre::eyre::Result<()> {
+/// let client = Client::with_uri_str("mongodb://localhost:27017").await?;
+/// let db = client.database("app");
+/// let options = RepairOptions::default().build();
+/// let plan =
+///     BatchRepairPlan::compute(db.collection::<bson::RawDocumentBuf>("users"), &options).await?;
+/// print!("{plan}");
+/// plan.apply(&db).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BatchRepairPlan {
+    pub collection: String,
+    /// Changes per repaired document.
+    pub changes: Vec<RepairDiff>,
+}
+
+impl BatchRepairPlan {
+    /// Repair `collection` with `options` in a dry run, without asking for
+    /// confirmation, and collect the changes.
+    pub async fn compute(
+        collection: mongodb::Collection<bson::RawDocumentBuf>,
+        options: &RepairOptions,
+    ) -> crate::Result<Self> {
+        let options = options
+            .clone()
+            .dry_run(true)
+            .confirmation(AlwaysConfirm)
+            .build();
+        let report = fix_collection(collection, &options).await?;
+        Ok(Self {
+            collection: report.collection,
+            changes: report.changes,
+        })
+    }
+
+    /// Apply all changes to the collection of `db` with bulk `update` commands,
+    /// sending at most [`MAX_BATCH_SIZE`] updates at once.
+    ///
+    /// Returns the number of documents that were updated.
+    pub async fn apply(&self, db: &mongodb::Database) -> crate::Result<u64> {
+        let mut updates = Vec::new();
+        for document in &self.changes {
+            let Ok(id) = bson::oid::ObjectId::parse_str(&document.id) else {
+                eprintln!(
+                    "collection = {: <20} id = {: <30} SKIPPED (not an object id)",
+                    document.collection, document.id
+                );
+                continue;
+            };
+            updates.push(bson::doc! {"q": {"_id": id}, "u": update_document(document)});
+        }
+        let mut updated = 0;
+        for batch in updates.chunks(MAX_BATCH_SIZE) {
+            let result = db
+                .run_command(bson::doc! {
+                    "update": &self.collection,
+                    "updates": batch.to_vec(),
+                    "ordered": false,
+                })
+                .await?;
+            if let Ok(errors) = result.get_array("writeErrors") {
+                let message = errors
+                    .first()
+                    .and_then(bson::Bson::as_document)
+                    .and_then(|error| error.get_str("errmsg").ok())
+                    .unwrap_or_default();
+                return Err(RepairError::Other(format!(
+                    "{} of {} updates of collection {} failed: {message}",
+                    errors.len(),
+                    batch.len(),
+                    self.collection
+                )));
+            }
+            updated += u64::try_from(result.get_i32("nModified").unwrap_or_default())?;
+        }
+        println!(
+            "collection = {: <20} APPLIED plan, updated {updated} documents",
+            self.collection
+        );
+        Ok(updated)
+    }
+}
+
+impl fmt::Display for BatchRepairPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "collection = {: <20} PLAN ({} documents)",
+            self.collection,
+            self.changes.len()
+        )?;
+        for document in &self.changes {
+            write!(f, "{document}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::IsTerminal;
+
+/// Progress of repairing a single collection, reported on stderr.
+///
+/// Draws a progress bar when stderr is a terminal and otherwise prints
+/// a line every `interval` documents, e.g. for CI logs.
+pub(crate) enum Progress {
+    Bar(indicatif::ProgressBar),
+    Lines {
+        collection: String,
+        total: u64,
+        processed: u64,
+        interval: u64,
+    },
+    Hidden,
+}
+
+impl Progress {
+    /// Report the progress over `total` documents of `collection`.
+    ///
+    /// An `interval` of zero disables progress reporting.
+    pub(crate) fn new(collection: &str, total: u64, interval: u64) -> Self {
+        if interval == 0 {
+            Self::Hidden
+        } else if std::io::stderr().is_terminal() {
+            let bar = indicatif::ProgressBar::new(total);
+            bar.set_style(
+                indicatif::ProgressStyle::with_template(
+                    "[{prefix}] {bar:40} {pos}/{len} ({percent}%) {elapsed_precise}",
+                )
+                .expect("progress template is valid"),
+            );
+            bar.set_prefix(collection.to_string());
+            Self::Bar(bar)
+        } else {
+            Self::Lines {
+                collection: collection.to_string(),
+                total,
+                processed: 0,
+                interval,
+            }
+        }
+    }
+
+    /// Count one processed document.
+    pub(crate) fn inc(&mut self) {
+        match self {
+            Self::Bar(bar) => bar.inc(1),
+            Self::Lines {
+                collection,
+                total,
+                processed,
+                interval,
+            } => {
+                *processed += 1;
+                if *processed % *interval == 0 {
+                    let percent = if *total == 0 {
+                        100.0
+                    } else {
+                        *processed as f64 / *total as f64 * 100.0
+                    };
+                    eprintln!("[{collection}] processed {processed}/{total} ({percent:.1}%)");
+                }
+            }
+            Self::Hidden => {}
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        if let Self::Bar(bar) = self {
+            bar.finish();
+        }
+    }
+}
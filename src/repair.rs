@@ -0,0 +1,2053 @@
+use futures::{StreamExt, TryStreamExt};
+use mongodb::bson;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::audit::AuditLog;
+use crate::constraint::{length_violation, LengthViolation};
+use crate::dedup::Claim;
+use crate::encoding::{byte_entropy, reinterpret_latin1_as_utf8, strict_utf8_violation};
+use crate::events::RepairEvent;
+use crate::lag::LagThrottle;
+use crate::progress::Progress;
+use crate::scan::BrokenIndex;
+use crate::split::split_document;
+use crate::stats::FieldRecord;
+use crate::timing::{Stage, TimingProfile};
+use crate::verify::BatchVerifier;
+use crate::{
+    normalize_legacy_uuid, AlwaysConfirm, ChangeKind, CollectionReport, ConstraintAction, Encoding,
+    FieldChange, LegacyUuidStyle, RepairDiff, RepairError, RepairMetrics, RepairOptions,
+    SplitStrategy,
+};
+
+/// Hex encoding of the first `max_bytes` of `bytes`, followed by `...` if truncated.
+fn hex_bytes(bytes: &[u8], max_bytes: usize) -> String {
+    let mut hex: String = bytes
+        .iter()
+        .take(max_bytes)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if bytes.len() > max_bytes {
+        hex.push_str("...");
+    }
+    hex
+}
+
+/// Collapse runs of whitespace in `value` into a single space and trim it,
+/// for [`RepairOptions::normalize_whitespace`].
+fn normalize_whitespace(value: &str) -> String {
+    static WHITESPACE: OnceLock<regex::Regex> = OnceLock::new();
+    let whitespace =
+        WHITESPACE.get_or_init(|| regex::Regex::new(r"\s+").expect("whitespace pattern is valid"));
+    whitespace.replace_all(value, " ").trim().to_string()
+}
+
+/// Repair the string values of a JSON `value` in place, returning whether any changed.
+fn repair_json_strings(value: &mut serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::String(string) => match reinterpret_latin1_as_utf8(string) {
+            Some(repaired) => {
+                *string = repaired;
+                true
+            }
+            None => false,
+        },
+        // every item is repaired, so do not short-circuit
+        serde_json::Value::Array(items) => {
+            items
+                .iter_mut()
+                .map(repair_json_strings)
+                .filter(|changed| *changed)
+                .count()
+                > 0
+        }
+        serde_json::Value::Object(fields) => {
+            fields
+                .values_mut()
+                .map(repair_json_strings)
+                .filter(|changed| *changed)
+                .count()
+                > 0
+        }
+        _ => false,
+    }
+}
+
+/// Repair the string values of the JSON object or array serialized in `value`,
+/// for [`RepairOptions::repair_embedded_json`].
+///
+/// Returns the re-serialized JSON, or `None` if `value` is no JSON or nothing changed.
+fn repair_embedded_json(value: &str) -> Option<String> {
+    let mut json: serde_json::Value = serde_json::from_str(value).ok()?;
+    if !(json.is_object() || json.is_array()) || !repair_json_strings(&mut json) {
+        return None;
+    }
+    serde_json::to_string(&json).ok()
+}
+
+/// Number of replacement characters (`U+FFFD`) in `value`.
+fn replacement_chars(value: &str) -> usize {
+    value.matches(char::REPLACEMENT_CHARACTER).count()
+}
+
+fn fix_string(
+    doc: &bson::RawDocument,
+    key: &str,
+    path: &str,
+    elem: &bson::raw::RawElement,
+    start: usize,
+    options: &RepairOptions,
+    tally: &mut FieldTally,
+) -> crate::Result<(bool, FieldChange, Encoding)> {
+    let bytes = doc.as_bytes();
+
+    let key_start = start + 4 + 1;
+    let raw_key = &bytes[key_start..key_start + key.len()];
+    assert_eq!(key, String::from_utf8_lossy(raw_key).to_string());
+
+    let value_start = key_start + key.len();
+    let raw_value = &bytes[value_start + 4 + 1..value_start + elem.len()];
+    let old_value_utf8 = String::from_utf8_lossy(raw_value).to_string();
+    let unchanged = |value: String| FieldChange {
+        path: path.to_string(),
+        old_value: value.clone(),
+        new_value: value,
+        kind: ChangeKind::Value,
+        raw_hex: options
+            .report_raw_bytes
+            .then(|| hex_bytes(raw_value, options.max_hex_bytes)),
+    };
+    if let Some(pattern) = &options.repair_regex {
+        if !pattern.is_match(&old_value_utf8) {
+            return Ok((false, unchanged(old_value_utf8), options.encoding));
+        }
+    }
+    let entropy = byte_entropy(raw_value);
+    let ascii_ratio =
+        raw_value.iter().filter(|byte| byte.is_ascii()).count() as f64 / raw_value.len() as f64;
+    let high_entropy = entropy > options.field_entropy_threshold;
+    let mostly_ascii = ascii_ratio > options.field_ascii_ratio_threshold;
+    if high_entropy && !options.quiet {
+        println!("[{path}] high-entropy ({entropy:.2} bits per byte), repair skipped");
+    } else if mostly_ascii && options.verbose {
+        println!(
+            "[{path}] {:.0}% ASCII bytes, repair skipped",
+            ascii_ratio * 100.0
+        );
+    }
+    if high_entropy || mostly_ascii {
+        return Ok((false, unchanged(old_value_utf8), options.encoding));
+    }
+    // println!("{key: >20} => {:#02x?}", raw_value);
+    // println!(
+    //     "{key: >20} => [utf8]{:?}",
+    //     String::from_utf8_lossy(raw_value)
+    // );
+    let (encoding, new_value_utf8) = if options.split_encoding {
+        let (encoding, decoded) = options.encoding.decode_best(raw_value);
+        if options.verbose {
+            println!("[{path}] decoded as {encoding}");
+        }
+        (encoding, decoded)
+    } else if let (Encoding::Script, Some(script)) = (options.encoding, &options.transform_script) {
+        (options.encoding, script.transform(raw_value, path)?)
+    } else {
+        (options.encoding, options.decode(raw_value))
+    };
+    if options.validate_utf8_strictly {
+        // replacement characters that were not already encoded in the original bytes
+        let encoded = raw_value
+            .windows(3)
+            .filter(|bytes| *bytes == "\u{fffd}".as_bytes())
+            .count();
+        let lossy = replacement_chars(&new_value_utf8) > encoded;
+        let violation = strict_utf8_violation(raw_value)
+            .or_else(|| lossy.then(|| format!("{encoding} decoding replaced invalid sequences")));
+        if let Some(reason) = violation {
+            return Err(RepairError::EncodingFailed {
+                field: path.to_string(),
+                reason,
+            });
+        }
+    }
+    let new_value_utf8 = match options
+        .repair_embedded_json
+        .then(|| repair_embedded_json(&new_value_utf8))
+        .flatten()
+    {
+        Some(repaired) => {
+            if options.verbose {
+                println!("[{path}] repaired embedded JSON");
+            }
+            repaired
+        }
+        None => new_value_utf8,
+    };
+    let new_value_utf8 = if options.normalize_whitespace {
+        let normalized = normalize_whitespace(&new_value_utf8);
+        if normalized != new_value_utf8 {
+            println!("[{path}] normalized whitespace {new_value_utf8:?} => {normalized:?}");
+        }
+        normalized
+    } else {
+        new_value_utf8
+    };
+    let new_value_utf8 = if options.graceful_degradation
+        && replacement_chars(&new_value_utf8) > replacement_chars(&old_value_utf8)
+    {
+        println!(
+            "[{path}] repair {new_value_utf8:?} has more replacement characters than {old_value_utf8:?}, keeping lossy UTF-8"
+        );
+        tally.degraded_fields += 1;
+        old_value_utf8.clone()
+    } else {
+        new_value_utf8
+    };
+    let mut change = FieldChange {
+        path: path.to_string(),
+        old_value: old_value_utf8,
+        new_value: new_value_utf8,
+        kind: ChangeKind::Value,
+        raw_hex: options
+            .report_raw_bytes
+            .then(|| hex_bytes(raw_value, options.max_hex_bytes)),
+    };
+
+    let hex_id = doc
+        .get_object_id("_id")
+        .ok()
+        .map(bson::oid::ObjectId::to_hex);
+
+    if let Some(max_ratio) = options.max_replacement_ratio {
+        let ratio = change.new_value.len() as f64 / raw_value.len().max(1) as f64;
+        if ratio > max_ratio {
+            eprintln!(
+                "[{}][{path}] WARNING repaired value is {ratio:.2} times as long as the original, not repairing",
+                hex_id.as_deref().unwrap_or("")
+            );
+            return Ok((false, change, encoding));
+        }
+    }
+
+    if let Some(violation) = length_violation(path, &change.new_value, options) {
+        match (options.constraint_violation_action, violation) {
+            (ConstraintAction::Error, _) => {
+                return Err(RepairError::ConstraintViolation {
+                    field: path.to_string(),
+                    reason: violation.to_string(),
+                });
+            }
+            (ConstraintAction::Truncate, LengthViolation::TooLong { max, .. }) => {
+                eprintln!(
+                    "[{}][{path}] WARNING {violation}, truncating",
+                    hex_id.as_deref().unwrap_or("")
+                );
+                change.new_value = change.new_value.chars().take(max).collect();
+            }
+            _ => {
+                eprintln!(
+                    "[{}][{path}] WARNING {violation}, not repairing",
+                    hex_id.as_deref().unwrap_or("")
+                );
+                return Ok((false, change, encoding));
+            }
+        }
+    }
+
+    // let prompt = format!(
+    //     "[{}][{key}] {old_value_utf8:?} => {new_value_utf8:?}",
+    //     hex_id.as_deref().unwrap_or(""),
+    // );
+    let prompt = format!("[{}] {change}", hex_id.as_deref().unwrap_or(""));
+    let confirmation = options.confirmation.confirm(&prompt)?;
+
+    if !options.count_only {
+        if confirmation {
+            println!("{}", &prompt);
+        } else {
+            println!("{prompt} (not confirmed)");
+        }
+        if let Some(raw_hex) = &change.raw_hex {
+            println!("  raw bytes: {raw_hex}");
+        }
+    }
+    Ok((confirmation, change, encoding))
+}
+
+/// Convert a legacy UUID `binary` written in `style` to a standard UUID, for
+/// [`RepairOptions::normalize_legacy_uuid`].
+fn fix_legacy_uuid(
+    doc: &bson::RawDocument,
+    path: &str,
+    binary: bson::RawBinaryRef<'_>,
+    style: LegacyUuidStyle,
+    options: &RepairOptions,
+) -> crate::Result<Option<(FieldChange, bson::Uuid)>> {
+    let legacy = bson::Binary {
+        subtype: binary.subtype,
+        bytes: binary.bytes.to_vec(),
+    };
+    let Some(uuid) = normalize_legacy_uuid(&legacy, style) else {
+        return Ok(None);
+    };
+    let hex_id = doc
+        .get_object_id("_id")
+        .map(|id| id.to_hex())
+        .unwrap_or_default();
+    let change = FieldChange {
+        path: path.to_string(),
+        old_value: hex_bytes(binary.bytes, binary.bytes.len()),
+        new_value: uuid.to_string(),
+        kind: ChangeKind::Uuid,
+        raw_hex: None,
+    };
+    let prompt = format!("[{hex_id}] {change} ({style})");
+    if !options.confirmation.confirm(&prompt)? {
+        if !options.count_only {
+            println!("{prompt} (not confirmed)");
+        }
+        return Ok(None);
+    }
+    if !options.count_only {
+        println!("{prompt}");
+    }
+    Ok(Some((change, uuid)))
+}
+
+/// Interpret the bytes of a generic binary value as text, for
+/// [`RepairOptions::include_binary_fields`].
+///
+/// Returns the change converting the value to a string if the bytes are text
+/// and [`RepairOptions::fix_binary_fields`] is set.
+fn fix_binary(
+    doc: &bson::RawDocument,
+    path: &str,
+    bytes: &[u8],
+    options: &RepairOptions,
+) -> crate::Result<Option<FieldChange>> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => options.decode(bytes),
+    };
+    let hex_id = doc
+        .get_object_id("_id")
+        .map(|id| id.to_hex())
+        .unwrap_or_default();
+    let is_text = text
+        .chars()
+        .all(|c| c != char::REPLACEMENT_CHARACTER && (!c.is_control() || c.is_whitespace()));
+    if !is_text {
+        if options.verbose {
+            println!("[{hex_id}][{path}] binary value is not text");
+        }
+        return Ok(None);
+    }
+    let prompt = format!("[{hex_id}][{path}] binary value => {text:?}");
+    if !options.fix_binary_fields {
+        if !options.count_only {
+            println!("{prompt} (not converted)");
+        }
+        return Ok(None);
+    }
+    if !options.confirmation.confirm(&prompt)? {
+        if !options.count_only {
+            println!("{prompt} (not confirmed)");
+        }
+        return Ok(None);
+    }
+    if !options.count_only {
+        println!("{prompt}");
+    }
+    Ok(Some(FieldChange {
+        path: path.to_string(),
+        old_value: String::from_utf8_lossy(bytes).to_string(),
+        new_value: text,
+        kind: ChangeKind::Value,
+        raw_hex: options
+            .report_raw_bytes
+            .then(|| hex_bytes(bytes, options.max_hex_bytes)),
+    }))
+}
+
+/// A field name that is not valid UTF-8, found by [`patch_keys`].
+struct RepairedKey {
+    raw: Vec<u8>,
+    /// Lossy UTF-8 decoding of the original name.
+    old: String,
+    new: String,
+}
+
+/// Repaired field names by element offset.
+type RepairedKeys = HashMap<usize, RepairedKey>;
+
+/// Replace field names of `doc` that are not valid UTF-8 with placeholders of the same length,
+/// so that the document can be iterated.
+///
+/// Returns the patched document and the repaired field names by element offset.
+fn patch_keys(
+    doc: &bson::RawDocument,
+    options: &RepairOptions,
+) -> crate::Result<(bson::RawDocumentBuf, RepairedKeys)> {
+    let mut bytes = doc.as_bytes().to_vec();
+    let mut keys = HashMap::new();
+    'patch: loop {
+        let mut start = 0;
+        for elem in bson::RawDocument::from_bytes(&bytes)?.iter_elements() {
+            match elem {
+                Ok(elem) => start += 1 + elem.key().len() + 1 + elem.len(),
+                Err(
+                    err @ bson::raw::Error {
+                        kind: bson::raw::ErrorKind::Utf8EncodingError(_),
+                        ..
+                    },
+                ) if err.key().is_none() => {
+                    let key_start = start + 4 + 1;
+                    let key_len =
+                        bytes[key_start..]
+                            .iter()
+                            .position(|b| *b == 0)
+                            .ok_or_else(|| {
+                                RepairError::Other("field name is not null terminated".to_string())
+                            })?;
+                    let raw_key = &mut bytes[key_start..key_start + key_len];
+                    keys.insert(
+                        start,
+                        RepairedKey {
+                            raw: raw_key.to_vec(),
+                            old: String::from_utf8_lossy(raw_key).to_string(),
+                            new: options.decode(raw_key),
+                        },
+                    );
+                    raw_key.fill(b'_');
+                    continue 'patch;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        return Ok((bson::RawDocumentBuf::from_bytes(bytes)?, keys));
+    }
+}
+
+/// Replace the placeholder name of the element at `offset` of `doc`, the last one,
+/// with the original `raw_key` bytes, which are not valid UTF-8.
+fn restore_raw_key(
+    doc: &mut bson::RawDocumentBuf,
+    offset: usize,
+    raw_key: &[u8],
+) -> crate::Result<()> {
+    let mut bytes = doc.as_bytes().to_vec();
+    bytes[offset + 1..offset + 1 + raw_key.len()].copy_from_slice(raw_key);
+    *doc = bson::RawDocumentBuf::from_bytes(bytes)?;
+    Ok(())
+}
+
+/// Append an element with the raw `value` bytes of `element_type` to `doc`, without parsing them.
+fn append_raw(
+    doc: &mut bson::RawDocumentBuf,
+    key: &str,
+    element_type: bson::spec::ElementType,
+    value: &[u8],
+) -> crate::Result<()> {
+    let mut bytes = doc.as_bytes().to_vec();
+    bytes.pop();
+    bytes.push(element_type as u8);
+    bytes.extend_from_slice(key.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(value);
+    bytes.push(0);
+    let len = i32::try_from(bytes.len())?;
+    bytes[..4].copy_from_slice(&len.to_le_bytes());
+    *doc = bson::RawDocumentBuf::from_bytes(bytes)?;
+    Ok(())
+}
+
+/// Copy `doc` into `new_doc`, repairing string values that are not valid UTF-8.
+///
+/// Only values of the [`RepairOptions::field_types`] are repaired.
+/// `path` holds the keys leading to `doc` (empty for a top-level document)
+/// and is used for the dot-notation paths of the changes.
+///
+/// Returns the values that were changed.
+pub fn fix_document(
+    doc: &bson::RawDocument,
+    new_doc: &mut bson::RawDocumentBuf,
+    options: &RepairOptions,
+    path: &mut Vec<String>,
+) -> crate::Result<Vec<FieldChange>> {
+    fix_document_counting(doc, new_doc, options, path, &mut FieldTally::default())
+}
+
+/// Fields of a document that were copied verbatim or decoded, counted by [`fix_document_counting`].
+#[derive(Debug, Default)]
+struct FieldTally {
+    /// Fields copied verbatim because of [`RepairOptions::ignore_fields_with_errors`].
+    parse_error_fields: u64,
+    /// Fields whose repair was replaced because of [`RepairOptions::graceful_degradation`].
+    degraded_fields: u64,
+    /// String values that were decoded, whether or not the repair was applied.
+    records: Vec<FieldRecord>,
+}
+
+/// Like [`fix_document`], counting the fields in `tally`.
+fn fix_document_counting(
+    doc: &bson::RawDocument,
+    new_doc: &mut bson::RawDocumentBuf,
+    options: &RepairOptions,
+    path: &mut Vec<String>,
+    tally: &mut FieldTally,
+) -> crate::Result<Vec<FieldChange>> {
+    let patched;
+    let mut repaired_keys = HashMap::new();
+    let doc = if options.repair_keys {
+        (patched, repaired_keys) = patch_keys(doc, options)?;
+        &patched
+    } else {
+        doc
+    };
+
+    let mut changes = Vec::new();
+    let mut renamed_keys = HashSet::new();
+    let mut start = 0;
+    for elem in doc.iter_elements() {
+        let elem = elem?;
+        // offset of the element appended to `new_doc`, whose name is restored to `raw_key`
+        let offset = new_doc.as_bytes().len() - 1;
+        let mut raw_key = None;
+        let key = match repaired_keys.remove(&start) {
+            Some(repaired) => {
+                let conflict = renamed_keys.contains(&repaired.new)
+                    || doc
+                        .iter_elements()
+                        .any(|other| other.is_ok_and(|other| other.key() == repaired.new));
+                if options.rename_repaired_keys && !conflict {
+                    path.push(repaired.new.clone());
+                    changes.push(FieldChange {
+                        path: path.join("."),
+                        old_value: repaired.old,
+                        new_value: repaired.new.clone(),
+                        kind: ChangeKind::Key,
+                        raw_hex: None,
+                    });
+                    renamed_keys.insert(repaired.new.clone());
+                    Cow::Owned(repaired.new)
+                } else {
+                    path.push(repaired.old.clone());
+                    let field_path = path.join(".");
+                    if options.rename_repaired_keys {
+                        eprintln!(
+                            "[{field_path}] WARNING repaired field name {:?} conflicts with an existing field, not renaming",
+                            repaired.new
+                        );
+                    } else if !options.quiet {
+                        println!(
+                            "[{field_path}] field name {:?} => {:?} (not renamed without --rename-repaired-keys)",
+                            repaired.old, repaired.new
+                        );
+                    }
+                    raw_key = Some(repaired.raw);
+                    Cow::Borrowed(elem.key())
+                }
+            }
+            None => {
+                path.push(elem.key().to_string());
+                Cow::Borrowed(elem.key())
+            }
+        };
+        let key = key.as_ref();
+        let field_path = path.join(".");
+        let value = elem.value();
+
+        if options
+            .field_blocklist
+            .as_ref()
+            .is_some_and(|blocklist| blocklist.contains(&field_path))
+        {
+            let value_start = start + 4 + 1 + elem.key().len() + 1;
+            let raw_value = &doc.as_bytes()[value_start..value_start + elem.len()];
+            append_raw(new_doc, key, elem.element_type(), raw_value)?;
+            if let Some(raw_key) = &raw_key {
+                restore_raw_key(new_doc, offset, raw_key)?;
+            }
+            start += 1 + elem.key().len() + 1 + elem.len();
+            path.pop();
+            continue;
+        }
+
+        if options.ignore_fields_with_errors {
+            if let Err(err) = &value {
+                if !matches!(err.kind, bson::raw::ErrorKind::Utf8EncodingError(_)) {
+                    eprintln!(
+                        "[{}][{field_path}] WARNING {err}, copying the field verbatim",
+                        doc.get_object_id("_id")
+                            .map(|id| id.to_hex())
+                            .unwrap_or_default()
+                    );
+                    let value_start = start + 4 + 1 + elem.key().len() + 1;
+                    let raw_value = &doc.as_bytes()[value_start..value_start + elem.len()];
+                    append_raw(new_doc, key, elem.element_type(), raw_value)?;
+                    if let Some(raw_key) = &raw_key {
+                        restore_raw_key(new_doc, offset, raw_key)?;
+                    }
+                    tally.parse_error_fields += 1;
+                    start += 1 + elem.key().len() + 1 + elem.len();
+                    path.pop();
+                    continue;
+                }
+            }
+        }
+
+        match elem.element_type() {
+            bson::spec::ElementType::EmbeddedDocument => {
+                let bson::raw::RawBsonRef::Document(subdoc) = value? else {
+                    unreachable!("element type is embedded document");
+                };
+                let mut new_subdoc = bson::RawDocumentBuf::new();
+                changes.extend(fix_document_counting(
+                    subdoc,
+                    &mut new_subdoc,
+                    options,
+                    path,
+                    tally,
+                )?);
+                new_doc.append(key, new_subdoc);
+            }
+            bson::spec::ElementType::Array => {
+                let bson::raw::RawBsonRef::Array(array) = value? else {
+                    unreachable!("element type is array");
+                };
+                let mut new_array = bson::raw::RawArrayBuf::new();
+                for (idx, item) in array.into_iter().enumerate() {
+                    match item? {
+                        bson::raw::RawBsonRef::Document(subdoc) => {
+                            let mut new_subdoc = bson::RawDocumentBuf::new();
+                            // address array items as `key[idx]` instead of `key`
+                            path.pop();
+                            path.push(format!("{key}[{idx}]"));
+                            changes.extend(fix_document_counting(
+                                subdoc,
+                                &mut new_subdoc,
+                                options,
+                                path,
+                                tally,
+                            )?);
+                            path.pop();
+                            path.push(key.to_string());
+                            new_array.push(new_subdoc);
+                        }
+                        bson::raw::RawBsonRef::String(value) => {
+                            // this is not good enough yet
+                            new_array.push(bson::RawBson::String(
+                                String::from_utf8_lossy(value.as_bytes()).to_string(),
+                            ));
+                        }
+                        other => {
+                            new_array.push(other.to_raw_bson());
+                        }
+                    }
+                }
+                new_doc.append(key, new_array);
+            }
+            element_type @ (bson::spec::ElementType::String
+            | bson::spec::ElementType::Symbol
+            | bson::spec::ElementType::JavaScriptCode)
+                if options.field_types.contains(&element_type) =>
+            {
+                if let Err(bson::raw::Error {
+                    kind: bson::raw::ErrorKind::Utf8EncodingError(_err),
+                    ..
+                }) = value
+                {
+                    let (fixed, change, encoding) =
+                        fix_string(doc, elem.key(), &field_path, &elem, start, options, tally)?;
+                    tally.records.push(FieldRecord::new(
+                        &field_path,
+                        encoding,
+                        fixed,
+                        &change.old_value,
+                        &change.new_value,
+                    ));
+                    let string_value = match element_type {
+                        bson::spec::ElementType::Symbol => bson::raw::RawBson::Symbol,
+                        bson::spec::ElementType::JavaScriptCode => {
+                            bson::raw::RawBson::JavaScriptCode
+                        }
+                        _ => bson::raw::RawBson::String,
+                    };
+                    if fixed {
+                        new_doc.append(key, string_value(change.new_value.clone()));
+                        changes.push(change);
+                    } else {
+                        new_doc.append(key, string_value(change.old_value));
+                    }
+                } else {
+                    new_doc.append(key, value?.to_raw_bson());
+                }
+            }
+            bson::spec::ElementType::Binary
+                if options.include_binary_fields || options.normalize_legacy_uuid.is_some() =>
+            {
+                let value = value?;
+                let change = match value {
+                    bson::raw::RawBsonRef::Binary(binary)
+                        if options.include_binary_fields
+                            && binary.subtype == bson::spec::BinarySubtype::Generic =>
+                    {
+                        fix_binary(doc, &field_path, binary.bytes, options)?.map(|change| {
+                            let text = bson::RawBson::String(change.new_value.clone());
+                            (change, text)
+                        })
+                    }
+                    bson::raw::RawBsonRef::Binary(binary) => match options.normalize_legacy_uuid {
+                        Some(style) => fix_legacy_uuid(doc, &field_path, binary, style, options)?
+                            .map(|(change, uuid)| {
+                                (change, bson::RawBson::Binary(bson::Binary::from_uuid(uuid)))
+                            }),
+                        None => None,
+                    },
+                    _ => None,
+                };
+                if let Some((change, new_value)) = change {
+                    new_doc.append(key, new_value);
+                    changes.push(change);
+                } else {
+                    new_doc.append(key, value.to_raw_bson());
+                }
+            }
+            _other => {
+                new_doc.append(key, value?.to_raw_bson());
+            }
+        }
+        if let Some(raw_key) = &raw_key {
+            restore_raw_key(new_doc, offset, raw_key)?;
+        }
+        start += 1 + elem.key().len() + 1 + elem.len();
+        path.pop();
+    }
+    Ok(changes)
+}
+
+/// Copy `doc`, replacing (or appending) the top-level field `key` with `value`.
+fn set_field(
+    doc: &bson::RawDocument,
+    key: &str,
+    value: bson::RawBson,
+) -> crate::Result<bson::RawDocumentBuf> {
+    let mut new_doc = bson::RawDocumentBuf::new();
+    for elem in doc {
+        let (elem_key, elem_value) = elem?;
+        if elem_key != key {
+            new_doc.append(elem_key, elem_value.to_raw_bson());
+        }
+    }
+    new_doc.append(key, value);
+    Ok(new_doc)
+}
+
+/// Remove the field at the dot-notation `path` of `doc`, returning its value.
+fn remove_path(doc: &mut bson::Document, path: &str) -> Option<bson::Bson> {
+    match path.split_once('.') {
+        Some((head, rest)) => remove_path(doc.get_document_mut(head).ok()?, rest),
+        None => doc.remove(path),
+    }
+}
+
+/// Set the field at the dot-notation `path` of `doc`, creating missing parent documents.
+fn insert_path(doc: &mut bson::Document, path: &str, value: bson::Bson) -> crate::Result<()> {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            let parent = doc
+                .entry(head.to_string())
+                .or_insert_with(|| bson::Bson::Document(bson::Document::new()));
+            let bson::Bson::Document(parent) = parent else {
+                return Err(RepairError::Other(format!(
+                    "cannot rename into {path}: {head} is not a document"
+                )));
+            };
+            insert_path(parent, rest, value)
+        }
+        None => {
+            doc.insert(path, value);
+            Ok(())
+        }
+    }
+}
+
+/// Apply the [`RepairOptions::rename_fields`] to `doc`, like `$rename`.
+///
+/// Returns the renamed document, or `None` if none of the fields is present.
+fn rename_fields(
+    doc: &bson::RawDocument,
+    options: &RepairOptions,
+) -> crate::Result<Option<(bson::RawDocumentBuf, Vec<FieldChange>)>> {
+    let mut renamed: bson::Document = doc.try_into()?;
+    let mut changes = Vec::new();
+    for (old_path, new_path) in &options.rename_fields {
+        let Some(value) = remove_path(&mut renamed, old_path) else {
+            continue;
+        };
+        insert_path(&mut renamed, new_path, value)?;
+        changes.push(FieldChange {
+            path: new_path.clone(),
+            old_value: old_path.clone(),
+            new_value: new_path.clone(),
+            kind: ChangeKind::Rename,
+            raw_hex: None,
+        });
+    }
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some((
+        bson::RawDocumentBuf::from_document(&renamed)?,
+        changes,
+    )))
+}
+
+/// Raw bytes of the top-level elements of `doc`, by key.
+fn raw_elements(doc: &bson::RawDocument) -> crate::Result<Vec<(&str, &[u8])>> {
+    let bytes = doc.as_bytes();
+    let mut start = 4;
+    doc.iter_elements()
+        .map(|elem| {
+            let elem = elem?;
+            let end = start + 1 + elem.key().len() + 1 + elem.len();
+            let raw = (elem.key(), &bytes[start..end]);
+            start = end;
+            Ok(raw)
+        })
+        .collect()
+}
+
+/// Merge the top-level fields of a repaired `projected` document into `full`.
+///
+/// The other fields of `full` are copied byte for byte, so they are
+/// neither parsed nor repaired.
+fn merge_projected(
+    full: &bson::RawDocument,
+    projected: &bson::RawDocument,
+) -> crate::Result<bson::RawDocumentBuf> {
+    let full = raw_elements(full)?;
+    let projected = raw_elements(projected)?;
+    let repaired: HashMap<_, _> = projected.iter().copied().collect();
+    let mut bytes = vec![0; 4];
+    for (key, raw) in &full {
+        bytes.extend_from_slice(repaired.get(key).unwrap_or(raw));
+    }
+    for (key, raw) in &projected {
+        if !full.iter().any(|(full_key, _)| full_key == key) {
+            bytes.extend_from_slice(raw);
+        }
+    }
+    bytes.push(0);
+    let len = i32::try_from(bytes.len())?;
+    bytes[..4].copy_from_slice(&len.to_le_bytes());
+    Ok(bson::RawDocumentBuf::from_bytes(bytes)?)
+}
+
+/// Unified diff of the pretty-printed `old` and `new` documents.
+fn document_diff(old: &bson::Document, new: &bson::Document, context_lines: usize) -> String {
+    let old = format!("{old:#?}\n");
+    let new = format!("{new:#?}\n");
+    similar::TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .context_radius(context_lines)
+        .to_string()
+}
+
+/// Validate `doc` against `schema`, returning the validation errors.
+fn schema_errors(
+    schema: &jsonschema::Validator,
+    doc: &bson::RawDocument,
+) -> crate::Result<Vec<String>> {
+    let instance = bson::Bson::Document(doc.try_into()?).into_relaxed_extjson();
+    Ok(schema
+        .iter_errors(&instance)
+        .map(|err| err.to_string())
+        .collect())
+}
+
+/// Load the `$jsonSchema` validator of `collection`, if it has one.
+///
+/// Validators using query operators cannot be checked client-side and are ignored.
+async fn collection_validator<T: Send + Sync>(
+    collection: &mongodb::Collection<T>,
+) -> crate::Result<Option<jsonschema::Validator>> {
+    let namespace = collection.namespace();
+    let response = collection
+        .client()
+        .database(&namespace.db)
+        .run_command(bson::doc! {
+            "listCollections": 1,
+            "filter": { "name": &namespace.coll },
+        })
+        .await?;
+    let validator = response
+        .get_document("cursor")?
+        .get_array("firstBatch")?
+        .iter()
+        .find_map(|info| info.as_document()?.get_document("options").ok())
+        .and_then(|options| options.get_document("validator").ok());
+    let Some(validator) = validator else {
+        return Ok(None);
+    };
+    let Ok(json_schema) = validator.get_document("$jsonSchema") else {
+        eprintln!(
+            "collection = {: <20} validator has no $jsonSchema and cannot be checked",
+            namespace.coll
+        );
+        return Ok(None);
+    };
+    let json_schema = bson::Bson::Document(json_schema.clone()).into_relaxed_extjson();
+    let validator = jsonschema::validator_for(&json_schema).map_err(|err| {
+        RepairError::Other(format!(
+            "invalid $jsonSchema validator of collection {}: {err}",
+            namespace.coll
+        ))
+    })?;
+    Ok(Some(validator))
+}
+
+/// A repaired document ready to be written.
+pub(crate) struct RepairedDocument {
+    /// Replacement for the original document.
+    pub(crate) document: bson::RawDocumentBuf,
+    pub(crate) changes: RepairDiff,
+    /// Additional documents to insert, split off an oversized document.
+    pub(crate) parts: Vec<bson::RawDocumentBuf>,
+    /// Fields copied verbatim because they could not be parsed.
+    pub(crate) parse_error_fields: u64,
+    /// Fields whose repair was replaced by the lossy UTF-8 decoding.
+    pub(crate) degraded_fields: u64,
+}
+
+/// Outcome of repairing a single document.
+pub(crate) enum DocumentOutcome {
+    /// No value needed repair.
+    Unchanged {
+        /// Fields copied verbatim because they could not be parsed.
+        parse_error_fields: u64,
+        /// Fields whose repair was replaced by the lossy UTF-8 decoding.
+        degraded_fields: u64,
+    },
+    /// The document was excluded, or its repaired values must not be written.
+    Skipped,
+    /// The document could not be repaired.
+    Failed,
+    Repaired(Box<RepairedDocument>),
+}
+
+impl DocumentOutcome {
+    /// Count this outcome in `metrics`.
+    pub(crate) fn record(&self, metrics: &mut RepairMetrics) {
+        metrics.total_docs += 1;
+        match self {
+            Self::Unchanged {
+                parse_error_fields,
+                degraded_fields,
+            } => {
+                metrics.parse_error_fields += parse_error_fields;
+                metrics.degraded_fields += degraded_fields;
+            }
+            Self::Skipped => metrics.skipped_docs += 1,
+            Self::Failed => metrics.error_docs += 1,
+            Self::Repaired(repaired) => {
+                metrics.changed_docs += 1;
+                metrics.total_fields_fixed += repaired.changes.field_changes.len() as u64;
+                metrics.parse_error_fields += repaired.parse_error_fields;
+                metrics.degraded_fields += repaired.degraded_fields;
+            }
+        }
+    }
+
+    /// Fail if the document failed and [`RepairOptions::abort_on_error`] is set.
+    pub(crate) fn check_abort(
+        &self,
+        collection_name: &str,
+        options: &RepairOptions,
+    ) -> crate::Result<()> {
+        if options.abort_on_error && matches!(self, Self::Failed) {
+            return Err(RepairError::Other(format!(
+                "aborting after a document of collection {collection_name} failed"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Repaired values of a document, before the checks of [`finish_repair`].
+pub(crate) struct RepairedFields {
+    pub(crate) id: String,
+    pub(crate) document: bson::RawDocumentBuf,
+    pub(crate) changes: Vec<FieldChange>,
+    pub(crate) parse_error_fields: u64,
+    pub(crate) degraded_fields: u64,
+}
+
+/// Print the diff between the original `raw_doc` and the repaired `new_raw_doc`.
+fn print_document_diff(
+    collection_name: &str,
+    id: &str,
+    raw_doc: &bson::RawDocument,
+    new_raw_doc: &bson::RawDocument,
+    changes: &[FieldChange],
+    options: &RepairOptions,
+) {
+    let print_unchanged = !options.quiet && (!options.skip_unchanged || options.verbose);
+    if print_unchanged || !changes.is_empty() {
+        println!("collection = {: <20} id = {: <30}", collection_name, id);
+    }
+
+    let doc = bson::Document::try_from(raw_doc);
+    let fixed_doc = bson::Document::try_from(new_raw_doc);
+
+    match (&doc, &fixed_doc) {
+        (Ok(doc), Ok(fixed_doc)) => {
+            // print!("{}", Comparison::new(&doc, &fixed_doc));
+            if doc != fixed_doc {
+                print!(
+                    "{}",
+                    document_diff(doc, fixed_doc, options.diff_context_lines)
+                );
+            }
+        }
+        (Err(_doc), Ok(_fixed_doc)) => {
+            // fine
+        }
+        (doc, fixed_doc) => {
+            println!("{:?}", doc);
+            println!("{:?}", fixed_doc);
+        }
+    }
+}
+
+/// Repair the repaired `new_raw_doc` again and log an error if that changes it,
+/// for [`RepairOptions::test_idempotency`].
+fn check_idempotency(
+    collection_name: &str,
+    id: &str,
+    new_raw_doc: &bson::RawDocument,
+    options: &RepairOptions,
+) {
+    // the second pass must neither prompt nor be counted
+    let mut options = options.clone().confirmation(AlwaysConfirm);
+    options.field_stats = None;
+    let mut twice = bson::RawDocumentBuf::new();
+    if let Err(err) = fix_document(new_raw_doc, &mut twice, &options, &mut Vec::new()) {
+        eprintln!(
+            "collection = {: <20} id = {: <30} ERROR repairing the repaired document failed: {err}",
+            collection_name, id
+        );
+        return;
+    }
+    if twice.as_bytes() == new_raw_doc.as_bytes() {
+        return;
+    }
+    eprintln!(
+        "collection = {: <20} id = {: <30} ERROR repair is not idempotent",
+        collection_name, id
+    );
+    if let (Ok(once), Ok(twice)) = (
+        bson::Document::try_from(new_raw_doc),
+        bson::Document::try_from(twice.as_ref()),
+    ) {
+        eprint!(
+            "{}",
+            document_diff(&once, &twice, options.diff_context_lines)
+        );
+    }
+}
+
+/// Repair the values of a single document of `collection_name` and print the resulting diff.
+/// Returns the final outcome instead if nothing was repaired, or
+/// [`RepairError::ConfirmationAborted`] if a confirmation prompt was aborted.
+pub(crate) fn repair_fields(
+    collection_name: &str,
+    raw_doc: &bson::RawDocument,
+    options: &RepairOptions,
+) -> crate::Result<Result<RepairedFields, DocumentOutcome>> {
+    let mut new_raw_doc = bson::raw::RawDocumentBuf::new();
+
+    let id = raw_doc
+        .get_object_id("_id")
+        .ok()
+        .map(bson::oid::ObjectId::to_hex)
+        .unwrap_or_default();
+
+    if let Some(pattern) = &options.skip_regex {
+        if pattern.is_match(&id) {
+            if !options.quiet {
+                println!(
+                    "collection = {: <20} id = {: <30} SKIPPED (id matches skip pattern)",
+                    collection_name, id
+                );
+            }
+            return Ok(Err(DocumentOutcome::Skipped));
+        }
+    }
+
+    let mut tally = FieldTally::default();
+    let changes = match fix_document_counting(
+        raw_doc,
+        &mut new_raw_doc,
+        options,
+        &mut Vec::new(),
+        &mut tally,
+    ) {
+        Ok(changes) => changes,
+        // the remaining documents would fail the same way
+        Err(RepairError::ConfirmationAborted) => return Err(RepairError::ConfirmationAborted),
+        Err(err) => {
+            eprintln!(
+                "collection = {: <20} id = {: <30} FAILED {err}",
+                collection_name, id
+            );
+            return Ok(Err(DocumentOutcome::Failed));
+        }
+    };
+    if let Some(field_stats) = &options.field_stats {
+        if let Err(err) = field_stats.record(collection_name, &tally.records) {
+            eprintln!(
+                "collection = {: <20} id = {: <30} FAILED {err}",
+                collection_name, id
+            );
+            return Ok(Err(DocumentOutcome::Failed));
+        }
+    }
+    let parse_error_fields = tally.parse_error_fields;
+    let degraded_fields = tally.degraded_fields;
+    if !changes.is_empty() && changes.len() < options.min_changed_fields {
+        eprintln!(
+            "collection = {: <20} id = {: <30} SKIPPED (only {} changed fields, fewer than {})",
+            collection_name,
+            id,
+            changes.len(),
+            options.min_changed_fields
+        );
+        return Ok(Err(DocumentOutcome::Skipped));
+    }
+    if options.test_idempotency && !changes.is_empty() {
+        check_idempotency(collection_name, &id, &new_raw_doc, options);
+    }
+    if !options.count_only {
+        print_document_diff(
+            collection_name,
+            &id,
+            raw_doc,
+            &new_raw_doc,
+            &changes,
+            options,
+        );
+    }
+
+    if changes.is_empty() {
+        return Ok(Err(DocumentOutcome::Unchanged {
+            parse_error_fields,
+            degraded_fields,
+        }));
+    }
+    Ok(Ok(RepairedFields {
+        id,
+        document: new_raw_doc,
+        changes,
+        parse_error_fields,
+        degraded_fields,
+    }))
+}
+
+/// Add the metadata fields to repaired `fields` and check the result
+/// against the size limit and schema.
+pub(crate) fn finish_repair(
+    collection_name: &str,
+    fields: RepairedFields,
+    options: &RepairOptions,
+) -> crate::Result<DocumentOutcome> {
+    let RepairedFields {
+        id,
+        document: mut new_raw_doc,
+        mut changes,
+        parse_error_fields,
+        degraded_fields,
+    } = fields;
+    if !options.rename_fields.is_empty() {
+        if let Some((renamed, renames)) = rename_fields(&new_raw_doc, options)? {
+            new_raw_doc = renamed;
+            changes.extend(renames);
+        }
+    }
+    if let Some(field) = &options.update_timestamp_field {
+        new_raw_doc = set_field(
+            &new_raw_doc,
+            field,
+            bson::RawBson::DateTime(bson::DateTime::now()),
+        )?;
+    }
+    if let Some(updated_by) = &options.updated_by {
+        new_raw_doc = set_field(
+            &new_raw_doc,
+            "updatedBy",
+            bson::RawBson::String(updated_by.clone()),
+        )?;
+    }
+    let mut parts = Vec::new();
+    let size = new_raw_doc.as_bytes().len();
+    if size > options.max_document_size {
+        if let Some(strategy @ (SplitStrategy::Truncate | SplitStrategy::SplitArray)) =
+            options.split_strategy
+        {
+            let split = split_document(&new_raw_doc, options.max_document_size, strategy)?;
+            eprintln!(
+                "collection = {: <20} id = {: <30} WARNING repaired document is {size} bytes, {} {} array items",
+                collection_name,
+                id,
+                if strategy == SplitStrategy::Truncate { "dropped" } else { "split off" },
+                split.moved
+            );
+            new_raw_doc = split.document;
+            parts = split.parts;
+        } else if options.strict_size || options.split_strategy == Some(SplitStrategy::Error) {
+            return Err(RepairError::DocumentTooLarge { id, size });
+        } else {
+            eprintln!(
+                "collection = {: <20} id = {: <30} SKIPPED (repaired document is {size} bytes, exceeding the limit of {} bytes)",
+                collection_name, id, options.max_document_size
+            );
+            return Ok(DocumentOutcome::Skipped);
+        }
+    } else if size > options.max_document_size / 10 * 9 {
+        eprintln!(
+            "collection = {: <20} id = {: <30} WARNING repaired document is {size} bytes, close to the limit of {} bytes",
+            collection_name, id, options.max_document_size
+        );
+    }
+    if let Some(schema) = &options.schema {
+        let errors = schema_errors(schema, &new_raw_doc)?;
+        if !errors.is_empty() {
+            eprintln!(
+                "collection = {: <20} id = {: <30} SKIPPED (schema validation failed)",
+                collection_name, id
+            );
+            for err in errors {
+                eprintln!("  {err}");
+            }
+            return Ok(DocumentOutcome::Skipped);
+        }
+    }
+    if let Some(repaired_values) = &options.deduplicate_repaired {
+        if let Some(change) = changes.iter_mut().find(|change| {
+            change.kind == ChangeKind::Value && change.path == repaired_values.field()
+        }) {
+            match repaired_values.claim(collection_name, &id, &change.new_value)? {
+                Claim::Unique => {}
+                Claim::Duplicate => {
+                    eprintln!(
+                        "collection = {: <20} id = {: <30} SKIPPED (duplicate repaired value)",
+                        collection_name, id
+                    );
+                    return Ok(DocumentOutcome::Skipped);
+                }
+                Claim::Suffixed(value) => {
+                    let mut doc: bson::Document = new_raw_doc.as_ref().try_into()?;
+                    insert_path(&mut doc, &change.path, bson::Bson::String(value.clone()))?;
+                    new_raw_doc = bson::RawDocumentBuf::from_document(&doc)?;
+                    change.new_value = value;
+                }
+            }
+        }
+    }
+    Ok(DocumentOutcome::Repaired(Box::new(RepairedDocument {
+        document: new_raw_doc,
+        changes: RepairDiff {
+            collection: collection_name.to_string(),
+            id,
+            field_changes: changes,
+        },
+        parts,
+        parse_error_fields,
+        degraded_fields,
+    })))
+}
+
+/// Repair a single document of `collection_name` and print the resulting diff.
+pub(crate) fn repair_document(
+    collection_name: &str,
+    raw_doc: &bson::RawDocument,
+    options: &RepairOptions,
+) -> crate::Result<DocumentOutcome> {
+    match repair_fields(collection_name, raw_doc, options)? {
+        Ok(fields) => finish_repair(collection_name, fields, options),
+        Err(outcome) => Ok(outcome),
+    }
+}
+
+/// Replace the document matching `filter` of `collection` with `document`.
+async fn replace_document(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    filter: bson::Document,
+    document: &bson::RawDocumentBuf,
+    options: &RepairOptions,
+) -> mongodb::error::Result<Option<bson::RawDocumentBuf>> {
+    #[cfg(debug_assertions)]
+    if let Some(failure) = &options.simulate_write_failure {
+        failure.write(collection.name())?;
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = options;
+    collection.find_one_and_replace(filter, document).await
+}
+
+/// Filter matching `raw_doc` by `_id` and, if set, [`RepairOptions::shard_key`]
+/// and [`RepairOptions::version_field`].
+fn replacement_filter(
+    raw_doc: &bson::RawDocument,
+    id: bson::oid::ObjectId,
+    options: &RepairOptions,
+) -> crate::Result<bson::Document> {
+    let mut filter = bson::doc! {"_id": id};
+    if let Some(shard_key) = &options.shard_key {
+        match raw_doc.get(shard_key)? {
+            Some(value) => {
+                filter.insert(shard_key, bson::Bson::try_from(value.to_raw_bson())?);
+            }
+            None => {
+                return Err(RepairError::Other(format!(
+                    "document {} has no shard key field {shard_key}",
+                    id.to_hex()
+                )))
+            }
+        }
+    }
+    if let Some(field) = &options.version_field {
+        match raw_doc.get(field)? {
+            Some(version) => {
+                filter.insert(field, bson::Bson::try_from(version.to_raw_bson())?);
+            }
+            None => {
+                filter.insert(field, bson::doc! {"$exists": false});
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// Copy `doc` with the [`RepairOptions::version_field`] `field` incremented,
+/// starting at 1 if it is missing.
+fn increment_version(doc: &bson::RawDocument, field: &str) -> crate::Result<bson::RawDocumentBuf> {
+    let invalid = |reason: String| RepairError::InvalidVersion {
+        field: field.to_string(),
+        reason,
+    };
+    let overflow = || invalid("version overflows".to_string());
+    let version = match doc.get(field)? {
+        None => bson::RawBson::Int32(1),
+        Some(bson::raw::RawBsonRef::Int32(version)) => {
+            bson::RawBson::Int32(version.checked_add(1).ok_or_else(overflow)?)
+        }
+        Some(bson::raw::RawBsonRef::Int64(version)) => {
+            bson::RawBson::Int64(version.checked_add(1).ok_or_else(overflow)?)
+        }
+        Some(bson::raw::RawBsonRef::Double(version)) => bson::RawBson::Double(version + 1.0),
+        Some(other) => return Err(invalid(format!("not a number: {other:?}"))),
+    };
+    set_field(doc, field, version)
+}
+
+/// Collection of the [`RepairOptions::destination`] database that documents of `collection` are written to.
+fn destination_collection(
+    destination: &mongodb::Database,
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    options: &RepairOptions,
+) -> mongodb::Collection<bson::RawDocumentBuf> {
+    destination.collection(
+        options
+            .destination_collection
+            .as_deref()
+            .unwrap_or(collection.name()),
+    )
+}
+
+/// Upsert `document` into `target` by `_id` and insert the split off `parts`.
+async fn write_destination(
+    target: &mongodb::Collection<bson::RawDocumentBuf>,
+    document: &bson::RawDocumentBuf,
+    parts: &[bson::RawDocumentBuf],
+) -> crate::Result<()> {
+    let id = document
+        .get("_id")?
+        .ok_or_else(|| RepairError::Other("document without _id".to_string()))?;
+    target
+        .replace_one(
+            bson::doc! {"_id": bson::Bson::try_from(id.to_raw_bson())?},
+            document,
+        )
+        .upsert(true)
+        .await?;
+    if !parts.is_empty() {
+        target.insert_many(parts).await?;
+    }
+    Ok(())
+}
+
+/// Repair a document of `collection` and replace it unless in dry run mode.
+///
+/// If the document changed since it was read (according to
+/// [`RepairOptions::version_field`]), it is read and repaired again up to
+/// [`RepairOptions::max_retries`] times.
+async fn process_document(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    raw_doc: mongodb::error::Result<bson::RawDocumentBuf>,
+    options: &RepairOptions,
+    validator: Option<&jsonschema::Validator>,
+    timing: Option<&TimingProfile>,
+) -> crate::Result<DocumentOutcome> {
+    let mut raw_doc = raw_doc?;
+    if let Some(jq_filter) = &options.jq_filter {
+        if !jq_filter.matches(&raw_doc)? {
+            return Ok(DocumentOutcome::Skipped);
+        }
+    }
+    let mut retries = 0;
+    loop {
+        let id = raw_doc.get_object_id("_id");
+        let Some(outcome) =
+            try_process_document(collection, raw_doc, options, validator, timing).await?
+        else {
+            // only replacements filtered by the version field conflict
+            let id = id?;
+            if retries >= options.max_retries {
+                println!(
+                    "collection = {: <20} id = {: <30} SKIPPED (version changed {retries} times)",
+                    collection.name(),
+                    id.to_hex()
+                );
+                return Ok(DocumentOutcome::Skipped);
+            }
+            retries += 1;
+            println!(
+                "collection = {: <20} id = {: <30} RETRY (version changed)",
+                collection.name(),
+                id.to_hex()
+            );
+            let mut find = collection.find_one(bson::doc! {"_id": id});
+            if let Some(projection) = &options.projection {
+                find = find.projection(projection.clone());
+            }
+            let Some(current) = find.await? else {
+                eprintln!(
+                    "collection = {: <20} id = {: <30} NOT FOUND",
+                    collection.name(),
+                    id.to_hex()
+                );
+                return Ok(DocumentOutcome::Skipped);
+            };
+            raw_doc = current;
+            continue;
+        };
+        return Ok(outcome);
+    }
+}
+
+/// Repair a document of `collection` and replace it unless in dry run mode.
+///
+/// Returns `None` if the replacement found a different [`RepairOptions::version_field`].
+async fn try_process_document(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    mut raw_doc: bson::RawDocumentBuf,
+    options: &RepairOptions,
+    validator: Option<&jsonschema::Validator>,
+    timing: Option<&TimingProfile>,
+) -> crate::Result<Option<DocumentOutcome>> {
+    let started = Instant::now();
+    let outcome = if options.projection.is_some() {
+        match repair_fields(collection.name(), &raw_doc, options)? {
+            Ok(mut fields) => {
+                // the projected document lacks the other fields, so merge the
+                // repaired fields into the full document read in a second query
+                let id = raw_doc.get_object_id("_id")?;
+                let Some(full) = collection.find_one(bson::doc! {"_id": id}).await? else {
+                    eprintln!(
+                        "collection = {: <20} id = {: <30} NOT FOUND",
+                        collection.name(),
+                        fields.id
+                    );
+                    return Ok(Some(DocumentOutcome::Skipped));
+                };
+                fields.document = merge_projected(&full, &fields.document)?;
+                raw_doc = full;
+                finish_repair(collection.name(), fields, options)?
+            }
+            Err(outcome) => outcome,
+        }
+    } else {
+        repair_document(collection.name(), &raw_doc, options)?
+    };
+    let Some(timing) = timing else {
+        return write_outcome(collection, &raw_doc, outcome, options, validator).await;
+    };
+    timing.record(Stage::Fix, started.elapsed());
+    let started = Instant::now();
+    let outcome = write_outcome(collection, &raw_doc, outcome, options, validator).await;
+    timing.record(Stage::Write, started.elapsed());
+    outcome
+}
+
+/// Write the `outcome` of repairing `raw_doc` of `collection`, unless in dry run mode.
+///
+/// Returns `None` if the replacement found a different [`RepairOptions::version_field`].
+async fn write_outcome(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    raw_doc: &bson::RawDocumentBuf,
+    outcome: DocumentOutcome,
+    options: &RepairOptions,
+    validator: Option<&jsonschema::Validator>,
+) -> crate::Result<Option<DocumentOutcome>> {
+    let DocumentOutcome::Repaired(repaired) = outcome else {
+        if let Some(output) = &options.output_bson {
+            if !options.only_changed {
+                output.write(raw_doc)?;
+            }
+        }
+        if let Some(destination) = &options.destination {
+            if !options.only_changed && !options.dry_run {
+                let target = destination_collection(destination, collection, options);
+                write_destination(&target, raw_doc, &[]).await?;
+            }
+        }
+        return Ok(Some(outcome));
+    };
+    if let Some(validator) = validator {
+        let errors = schema_errors(validator, &repaired.document)?;
+        if !errors.is_empty() {
+            return Err(RepairError::Other(format!(
+                "repaired document {} of collection {} violates the collection validator:\n  {}",
+                repaired.changes.id,
+                collection.name(),
+                errors.join("\n  ")
+            )));
+        }
+    }
+    if let Some(export) = &options.export_broken {
+        export.write(raw_doc)?;
+        return Ok(Some(DocumentOutcome::Repaired(repaired)));
+    }
+    if let Some(output) = &options.output_bson {
+        output.write(&repaired.document)?;
+        for part in &repaired.parts {
+            output.write(part)?;
+        }
+        return Ok(Some(DocumentOutcome::Repaired(repaired)));
+    }
+    if options.dry_run {
+        return Ok(Some(DocumentOutcome::Repaired(repaired)));
+    }
+    if let Some(destination) = &options.destination {
+        let target = destination_collection(destination, collection, options);
+        write_destination(&target, &repaired.document, &repaired.parts).await?;
+        println!(
+            "collection = {: <20} id = {: <30} WRITTEN to {}.{}",
+            collection.name(),
+            repaired.changes.id,
+            target.namespace().db,
+            target.name()
+        );
+        return Ok(Some(DocumentOutcome::Repaired(repaired)));
+    }
+
+    // replace the document
+    if let Ok(id) = raw_doc.get_object_id("_id") {
+        let filter = replacement_filter(raw_doc, id, options)?;
+        if options.verify_before_repair {
+            let current = collection.find_one(filter.clone()).await?;
+            if current.as_deref().map(bson::RawDocument::as_bytes) != Some(raw_doc.as_bytes()) {
+                println!(
+                    "collection = {: <20} id = {: <30} SKIPPED (modified concurrently)",
+                    collection.name(),
+                    id.to_hex()
+                );
+                return Ok(Some(DocumentOutcome::Skipped));
+            }
+        }
+        let document = match &options.version_field {
+            Some(field) => increment_version(&repaired.document, field)?,
+            None => repaired.document.clone(),
+        };
+        if options.noop_writes {
+            // prepare the writes, but only yield instead of sending them
+            let insert = collection.insert_many(&repaired.parts);
+            let replace = collection.find_one_and_replace(filter, &document);
+            drop((insert, replace));
+            tokio::task::yield_now().await;
+            println!(
+                "collection = {: <20} id = {: <30} REPLACED (no-op)",
+                collection.name(),
+                id.to_hex()
+            );
+            return Ok(Some(DocumentOutcome::Repaired(repaired)));
+        }
+        let replaced = match replace_document(collection, filter, &document, options).await {
+            Ok(replaced) => replaced,
+            Err(err) => {
+                // count as a failed document, so that the error budget applies
+                eprintln!(
+                    "collection = {: <20} id = {: <30} FAILED {err}",
+                    collection.name(),
+                    id.to_hex()
+                );
+                return Ok(Some(DocumentOutcome::Failed));
+            }
+        };
+        if replaced.is_none() && options.version_field.is_some() {
+            return Ok(None);
+        }
+        // the split off parts are only inserted once the replacement succeeded,
+        // so that failed or retried replacements leave no orphaned parts behind
+        if replaced.is_some() && !repaired.parts.is_empty() {
+            collection.insert_many(&repaired.parts).await?;
+        }
+        println!(
+            "collection = {: <20} id = {: <30} REPLACED",
+            collection.name(),
+            id.to_hex()
+        );
+    }
+    Ok(Some(DocumentOutcome::Repaired(repaired)))
+}
+
+/// Session reading the documents of `collection` at a single point in time, if the
+/// [`RepairOptions::read_concern`] is [`ReadConcern::snapshot`](mongodb::options::ReadConcern::snapshot).
+///
+/// Snapshot reads are only consistent within one session, which sets their cluster time.
+pub(crate) async fn snapshot_session(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    options: &RepairOptions,
+) -> crate::Result<Option<mongodb::ClientSession>> {
+    let snapshot = options.read_concern.as_ref().is_some_and(|read_concern| {
+        read_concern.level == mongodb::options::ReadConcernLevel::Snapshot
+    });
+    if !snapshot {
+        return Ok(None);
+    }
+    Ok(Some(
+        collection.client().start_session().snapshot(true).await?,
+    ))
+}
+
+/// Query plan of the cursor [`fix_collection`] reads the documents of `collection` with,
+/// as returned by the `explain` command with `verbosity`
+/// (`queryPlanner`, `executionStats` or `allPlansExecution`).
+///
+/// No documents are returned, but `executionStats` and `allPlansExecution` run the query.
+pub async fn explain_collection(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    options: &RepairOptions,
+    verbosity: &str,
+) -> crate::Result<bson::Document> {
+    let mut command = if options.randomize_order || !options.pipeline.is_empty() {
+        let mut pipeline = options.pipeline.clone();
+        if options.randomize_order {
+            let size = i64::try_from(collection.estimated_document_count().await?.max(1))?;
+            pipeline.push(bson::doc! {"$sample": {"size": size}});
+        }
+        if let Some(projection) = &options.projection {
+            pipeline.push(bson::doc! {"$project": projection.clone()});
+        }
+        bson::doc! {"aggregate": collection.name(), "pipeline": pipeline, "cursor": {}}
+    } else {
+        let mut find = bson::doc! {"find": collection.name(), "filter": {}};
+        if let Some(projection) = &options.projection {
+            find.insert("projection", projection.clone());
+        }
+        find
+    };
+    if let Some(hint) = &options.hint {
+        command.insert("hint", hint.clone());
+    }
+    let plan = collection
+        .client()
+        .database(&collection.namespace().db)
+        .run_command(bson::doc! {"explain": command, "verbosity": verbosity})
+        .await?;
+    Ok(plan)
+}
+
+/// Repair every document of `collection`, replacing changed documents unless
+/// [`RepairOptions::dry_run`] is set.
+///
+/// Returns metrics and the changes made (or proposed, in dry run mode) per document.
+///
+/// ```no_run
+/// use mongo_repair_utf8::{fix_collection, RepairOptions};
+/// use mongodb::{bson, Client};
+///
+/// # async fn run() -> color_eyre::eyre::Result<()> {
+/// let client = Client::with_uri_str("mongodb://localhost:27017").await?;
+/// let collection = client
+///     .database("app")
+///     .collection::<bson::RawDocumentBuf>("users");
+/// let options = RepairOptions::default().dry_run(true).build();
+/// fix_collection(collection, &options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn fix_collection(
+    collection: mongodb::Collection<bson::RawDocumentBuf>,
+    options: &RepairOptions,
+) -> crate::Result<CollectionReport> {
+    let started = Instant::now();
+    let mut report = CollectionReport::new(collection.name());
+    let validator = if options.collection_validator_check {
+        collection_validator(&collection).await?
+    } else {
+        None
+    };
+
+    let mut session = snapshot_session(&collection, options).await?;
+    let broken = if options.two_pass_scan {
+        Some(BrokenIndex::scan(&collection, options, session.as_mut()).await?)
+    } else {
+        None
+    };
+    let total = if let Some(broken) = &broken {
+        broken.len() as u64
+    } else if options.randomize_order {
+        // $sample must cover every document, so the estimate is not good enough
+        collection.count_documents(bson::doc! {}).await?
+    } else {
+        collection.estimated_document_count().await?
+    };
+    let mut progress = Progress::new(collection.name(), total, options.progress_interval);
+    let timing = options.timing_profile.then(TimingProfile::default);
+    let cursor_started = Instant::now();
+    let mut session_cursor = None;
+    let cursor = if let Some(broken) = &broken {
+        broken.documents(&collection, options, session.as_mut())
+    } else if options.randomize_order || !options.pipeline.is_empty() {
+        let mut pipeline = options.pipeline.clone();
+        if options.randomize_order {
+            let size = i64::try_from(total.max(1))?;
+            pipeline.push(bson::doc! {"$sample": {"size": size}});
+        }
+        if let Some(projection) = &options.projection {
+            pipeline.push(bson::doc! {"$project": projection.clone()});
+        }
+        let mut aggregate = collection.aggregate(pipeline).allow_disk_use(true);
+        if let Some(hint) = &options.hint {
+            aggregate = aggregate.hint(mongodb::options::Hint::Keys(hint.clone()));
+        }
+        if let Some(max_time) = options.cursor_max_time {
+            aggregate = aggregate.max_time(max_time);
+        }
+        match &mut session {
+            Some(session) => session_cursor
+                .insert(aggregate.session(&mut *session).await?.with_type())
+                .stream(session)
+                .boxed(),
+            None => {
+                if let Some(read_concern) = &options.read_concern {
+                    aggregate = aggregate.read_concern(read_concern.clone());
+                }
+                aggregate.await?.with_type::<bson::RawDocumentBuf>().boxed()
+            }
+        }
+    } else {
+        let mut find = collection.find(bson::doc! {});
+        if let Some(projection) = &options.projection {
+            find = find.projection(projection.clone());
+        }
+        if let Some(hint) = &options.hint {
+            find = find.hint(mongodb::options::Hint::Keys(hint.clone()));
+        }
+        if let Some(max_time) = options.cursor_max_time {
+            find = find.max_time(max_time);
+        }
+        match &mut session {
+            Some(session) => session_cursor
+                .insert(find.session(&mut *session).await?)
+                .stream(session)
+                .boxed(),
+            None => {
+                if let Some(read_concern) = &options.read_concern {
+                    find = find.read_concern(read_concern.clone());
+                }
+                find.await?.boxed()
+            }
+        }
+    };
+    if let Some(timing) = &timing {
+        timing.record(Stage::CursorOpen, cursor_started.elapsed());
+    }
+    let mut fetched = Instant::now();
+    let mut verifier = (options.validate_post_repair && options.writes_documents())
+        .then(|| BatchVerifier::new(options.bulk_find_batch));
+    let mut audit = options
+        .audit_collection
+        .clone()
+        .map(|audit_collection| AuditLog::new(audit_collection, options.dry_run));
+    let mut throttle = options
+        .replication_lag_tolerance
+        .filter(|_| options.writes_documents())
+        .map(|tolerance| LagThrottle::new(collection.client().clone(), tolerance));
+    let mut outcomes = cursor
+        .map(|raw_doc| {
+            if let Some(timing) = &timing {
+                timing.record(Stage::Fetch, fetched.elapsed());
+                fetched = Instant::now();
+            }
+            process_document(
+                &collection,
+                raw_doc,
+                options,
+                validator.as_ref(),
+                timing.as_ref(),
+            )
+        })
+        .buffer_unordered(options.parallel_documents);
+    while let Some(outcome) = outcomes.try_next().await? {
+        if let Some(blocklist) = &options.field_blocklist {
+            blocklist.reload_if_changed()?;
+        }
+        if let Some(throttle) = &mut throttle {
+            throttle.wait(collection.name()).await?;
+        }
+        progress.inc();
+        outcome.record(&mut report.metrics);
+        outcome.check_abort(collection.name(), options)?;
+        if options.max_errors_per_collection > 0
+            && report.metrics.error_docs >= options.max_errors_per_collection
+        {
+            eprintln!(
+                "collection = {: <20} WARNING stopping after {} failed documents",
+                collection.name(),
+                report.metrics.error_docs
+            );
+            report.metrics.collections_aborted = 1;
+            break;
+        }
+        if let Some(stream) = &options.report_stream {
+            let processed = report.metrics.total_docs;
+            if options.progress_interval > 0 && processed.is_multiple_of(options.progress_interval)
+            {
+                stream
+                    .send(&RepairEvent::Progress {
+                        collection: collection.name(),
+                        processed,
+                        total,
+                    })
+                    .await;
+            }
+        }
+        if let DocumentOutcome::Repaired(repaired) = outcome {
+            if let (Some(verifier), Ok(id)) = (
+                &mut verifier,
+                bson::oid::ObjectId::parse_str(&repaired.changes.id),
+            ) {
+                report.metrics.verification_failures += verifier.push(&collection, id).await?;
+            }
+            if let Some(audit) = &mut audit {
+                audit.push(&repaired.changes).await?;
+            }
+            if let Some(audit_file) = &options.audit_file {
+                audit_file.write(&repaired.changes, options.dry_run).await?;
+            }
+            if let Some(stream) = &options.report_stream {
+                stream
+                    .send(&RepairEvent::DocumentChanged {
+                        changes: &repaired.changes,
+                    })
+                    .await;
+            }
+            report.changes.push(repaired.changes);
+        }
+    }
+    let cursor_closed = Instant::now();
+    drop(outcomes);
+    if let Some(timing) = &timing {
+        timing.record(Stage::CursorClose, cursor_closed.elapsed());
+    }
+    if let Some(verifier) = &mut verifier {
+        report.metrics.verification_failures += verifier.flush(&collection).await?;
+    }
+    if let Some(audit) = &mut audit {
+        audit.flush().await?;
+    }
+    progress.finish();
+    if let Some(timing) = &timing {
+        timing.print(collection.name());
+    }
+    report.metrics.elapsed = started.elapsed();
+    report.check_changed_ratio(options);
+    if let Some(stream) = &options.report_stream {
+        stream
+            .send(&RepairEvent::CollectionFinished {
+                collection: collection.name(),
+                metrics: &report.metrics,
+            })
+            .await;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::make_corrupt_doc;
+    use crate::NeverConfirm;
+
+    fn raw(doc: bson::Document) -> bson::RawDocumentBuf {
+        bson::RawDocumentBuf::from_document(&doc).unwrap()
+    }
+
+    /// Repair `doc`, returning the changes and the repaired document.
+    fn repair(
+        doc: &bson::RawDocument,
+        options: &RepairOptions,
+    ) -> (Vec<FieldChange>, bson::RawDocumentBuf) {
+        let mut new_doc = bson::RawDocumentBuf::new();
+        let changes = fix_document(doc, &mut new_doc, options, &mut Vec::new()).unwrap();
+        (changes, new_doc)
+    }
+
+    #[test]
+    fn fix_document_repairs_utf16() {
+        let doc = make_corrupt_doc("name", b"\xe9t\xe9");
+        let (changes, new_doc) = repair(&doc, &RepairOptions::default().build());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "name");
+        assert_eq!(changes[0].old_value, "\u{fffd}t\u{fffd}");
+        assert_eq!(changes[0].new_value, "été");
+        assert_eq!(new_doc.get_str("name").unwrap(), "été");
+    }
+
+    #[test]
+    fn fix_document_copies_valid_documents() {
+        let doc = raw(bson::doc! { "name": "été", "count": 1 });
+        let (changes, new_doc) = repair(&doc, &RepairOptions::default().build());
+        assert!(changes.is_empty());
+        assert_eq!(new_doc.as_bytes(), doc.as_bytes());
+    }
+
+    #[test]
+    fn repair_regex_selects_values() {
+        let doc = make_corrupt_doc("name", b"\xe9t\xe9");
+        let options = RepairOptions::default()
+            .repair_regex(regex::Regex::new("^x").unwrap())
+            .build();
+        let (changes, new_doc) = repair(&doc, &options);
+        assert!(changes.is_empty());
+        assert_eq!(new_doc.get_str("name").unwrap(), "\u{fffd}t\u{fffd}");
+
+        let options = RepairOptions::default()
+            .repair_regex(regex::Regex::new("t").unwrap())
+            .build();
+        let (changes, _) = repair(&doc, &options);
+        assert_eq!(changes[0].new_value, "été");
+    }
+
+    #[test]
+    fn mostly_ascii_values_are_skipped() {
+        let doc = make_corrupt_doc("name", b"caf\xe9");
+        let (changes, _) = repair(&doc, &RepairOptions::default().build());
+        assert_eq!(changes[0].new_value, "café");
+
+        let options = RepairOptions::default()
+            .field_ascii_ratio_threshold(0.5)
+            .build();
+        let (changes, new_doc) = repair(&doc, &options);
+        assert!(changes.is_empty());
+        assert_eq!(new_doc.get_str("name").unwrap(), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn high_entropy_values_are_skipped() {
+        let bytes: Vec<u8> = (0x80..=0xff).collect();
+        let doc = make_corrupt_doc("data", &bytes);
+        let options = RepairOptions::default()
+            .field_entropy_threshold(6.0)
+            .build();
+        let (changes, new_doc) = repair(&doc, &options);
+        assert!(changes.is_empty());
+        assert_eq!(
+            new_doc.get_str("data").unwrap(),
+            "\u{fffd}".repeat(bytes.len())
+        );
+
+        let (changes, _) = repair(&doc, &RepairOptions::default().build());
+        assert_eq!(changes.len(), 1);
+    }
+
+    #[test]
+    fn confirmation_strategies() {
+        let doc = make_corrupt_doc("name", b"\xe9t\xe9");
+        let options = RepairOptions::default().confirmation(AlwaysConfirm).build();
+        let (changes, new_doc) = repair(&doc, &options);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(new_doc.get_str("name").unwrap(), "été");
+
+        let options = RepairOptions::default().confirmation(NeverConfirm).build();
+        let (changes, new_doc) = repair(&doc, &options);
+        assert!(changes.is_empty());
+        assert_eq!(new_doc.get_str("name").unwrap(), "\u{fffd}t\u{fffd}");
+    }
+
+    #[test]
+    fn increment_version_numbers() {
+        let doc = increment_version(&raw(bson::doc! { "v": 1_i32 }), "v").unwrap();
+        assert_eq!(doc.get_i32("v").unwrap(), 2);
+        let doc = increment_version(&raw(bson::doc! { "v": 1_i64 }), "v").unwrap();
+        assert_eq!(doc.get_i64("v").unwrap(), 2);
+        let doc = increment_version(&raw(bson::doc! { "v": 1.5 }), "v").unwrap();
+        assert_eq!(doc.get_f64("v").unwrap(), 2.5);
+        let doc = increment_version(&raw(bson::doc! { "name": "x" }), "v").unwrap();
+        assert_eq!(doc.get_i32("v").unwrap(), 1);
+    }
+
+    #[test]
+    fn increment_version_rejects_invalid_versions() {
+        for version in [
+            bson::Bson::Int32(i32::MAX),
+            bson::Bson::Int64(i64::MAX),
+            bson::Bson::String("1".to_string()),
+        ] {
+            let doc = raw(bson::doc! { "v": version });
+            assert!(matches!(
+                increment_version(&doc, "v"),
+                Err(RepairError::InvalidVersion { field, .. }) if field == "v"
+            ));
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    async fn simulated_write_failures_are_counted_as_failed() {
+        // prepend an `_id`, as documents without one are not replaced
+        let corrupt = make_corrupt_doc("name", b"caf\xe9");
+        let mut bytes = corrupt.as_bytes()[..4].to_vec();
+        bytes.push(bson::spec::ElementType::ObjectId as u8);
+        bytes.extend_from_slice(b"_id\0");
+        bytes.extend_from_slice(&bson::oid::ObjectId::new().bytes());
+        bytes.extend_from_slice(&corrupt.as_bytes()[4..]);
+        let len = i32::try_from(bytes.len()).unwrap();
+        bytes[..4].copy_from_slice(&len.to_le_bytes());
+        let doc = bson::RawDocumentBuf::from_bytes(bytes).unwrap();
+
+        // the simulated failure is returned before a server is selected
+        let client =
+            mongodb::Client::with_uri_str("mongodb://localhost:1/?serverSelectionTimeoutMS=100")
+                .await
+                .unwrap();
+        let collection = client.database("test").collection("users");
+        let options = RepairOptions::default()
+            .simulate_write_failure(1)
+            .quiet(true)
+            .build();
+        let outcome = process_document(&collection, Ok(doc), &options, None, None)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, DocumentOutcome::Failed));
+    }
+}
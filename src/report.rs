@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::time::Duration;
+
+use mongodb::bson;
+use serde::{Serialize, Serializer};
+
+use crate::{
+    probable_source_encoding, ChangeKind, Encoding, FieldChange, RepairDiff, RepairOptions,
+};
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Counters of a repair run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RepairMetrics {
+    /// Documents read.
+    pub total_docs: u64,
+    /// Documents with at least one repaired value.
+    pub changed_docs: u64,
+    /// Documents that could not be repaired.
+    pub error_docs: u64,
+    /// Documents excluded from the repair, or with repaired values that were not written.
+    pub skipped_docs: u64,
+    pub total_fields_fixed: u64,
+    /// Fields copied verbatim because they could not be parsed.
+    pub parse_error_fields: u64,
+    /// Fields whose repair was replaced by the lossy UTF-8 decoding, for
+    /// [`RepairOptions::graceful_degradation`](crate::RepairOptions::graceful_degradation).
+    pub degraded_fields: u64,
+    /// Replaced documents that were missing or still not valid UTF-8 when read back.
+    pub verification_failures: u64,
+    pub collections_processed: u64,
+    /// Collections abandoned after exceeding [`RepairOptions::collection_timeout`].
+    pub collections_timed_out: u64,
+    /// Collections abandoned after [`RepairOptions::max_errors_per_collection`] documents failed.
+    pub collections_aborted: u64,
+    /// Serialized in seconds.
+    #[serde(serialize_with = "serialize_secs")]
+    pub elapsed: Duration,
+}
+
+impl RepairMetrics {
+    /// Add the counters (and elapsed time) of `other`.
+    pub fn merge(&mut self, other: &RepairMetrics) {
+        self.total_docs += other.total_docs;
+        self.changed_docs += other.changed_docs;
+        self.error_docs += other.error_docs;
+        self.skipped_docs += other.skipped_docs;
+        self.total_fields_fixed += other.total_fields_fixed;
+        self.parse_error_fields += other.parse_error_fields;
+        self.degraded_fields += other.degraded_fields;
+        self.verification_failures += other.verification_failures;
+        self.collections_processed += other.collections_processed;
+        self.collections_timed_out += other.collections_timed_out;
+        self.collections_aborted += other.collections_aborted;
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl fmt::Display for RepairMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "collections = {} timed out = {} aborted = {} documents = {} changed = {} errors = {} skipped = {} fields fixed = {} fields skipped (parse error) = {} fields degraded = {} elapsed = {:.2?}",
+            self.collections_processed,
+            self.collections_timed_out,
+            self.collections_aborted,
+            self.total_docs,
+            self.changed_docs,
+            self.error_docs,
+            self.skipped_docs,
+            self.total_fields_fixed,
+            self.parse_error_fields,
+            self.degraded_fields,
+            self.elapsed
+        )
+    }
+}
+
+/// Outcome of repairing a single collection.
+#[derive(Debug, Clone, Default)]
+pub struct CollectionReport {
+    pub collection: String,
+    pub metrics: RepairMetrics,
+    /// Changes made (or proposed, in dry run mode) per changed document.
+    pub changes: Vec<RepairDiff>,
+}
+
+impl CollectionReport {
+    pub(crate) fn new(collection: impl Into<String>) -> Self {
+        Self {
+            collection: collection.into(),
+            metrics: RepairMetrics {
+                collections_processed: 1,
+                ..RepairMetrics::default()
+            },
+            changes: Vec::new(),
+        }
+    }
+
+    /// Warn if fewer documents were changed than [`RepairOptions::min_changed_ratio`].
+    pub(crate) fn check_changed_ratio(&self, options: &RepairOptions) {
+        let (Some(threshold), total @ 1..) = (options.min_changed_ratio, self.metrics.total_docs)
+        else {
+            return;
+        };
+        let ratio = self.metrics.changed_docs as f64 / total as f64;
+        if ratio < threshold {
+            eprintln!(
+                "collection = {: <20} WARNING only {:.2}% of documents changed (expected at least {:.2}%), check the encoding",
+                self.collection,
+                ratio * 100.0,
+                threshold * 100.0
+            );
+        }
+    }
+
+    /// Report of `collection`, abandoned after `elapsed` exceeded the time budget.
+    pub(crate) fn timed_out(collection: impl Into<String>, elapsed: Duration) -> Self {
+        Self {
+            collection: collection.into(),
+            metrics: RepairMetrics {
+                collections_timed_out: 1,
+                elapsed,
+                ..RepairMetrics::default()
+            },
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl CollectionReport {
+    /// Number of repaired values per [`probable_source_encoding`], most frequent first.
+    #[must_use]
+    pub fn source_encodings(&self) -> Vec<(&'static str, u64)> {
+        let mut counts: HashMap<&'static str, u64> = HashMap::new();
+        for change in self
+            .changes
+            .iter()
+            .flat_map(|document| &document.field_changes)
+            .filter(|change| change.kind == ChangeKind::Value)
+        {
+            *counts
+                .entry(probable_source_encoding(&change.new_value))
+                .or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts
+    }
+}
+
+/// Summary of a repair run over several collections.
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub database: String,
+    pub encoding: Encoding,
+    pub dry_run: bool,
+    pub started_at: bson::DateTime,
+    /// Totals over all collections, with the wall-clock time of the run.
+    pub metrics: RepairMetrics,
+    pub collections: Vec<CollectionReport>,
+}
+
+impl RunReport {
+    /// Summarize `collections` repaired with `options`.
+    #[must_use]
+    pub fn new(
+        database: impl Into<String>,
+        options: &RepairOptions,
+        started_at: bson::DateTime,
+        elapsed: Duration,
+        collections: Vec<CollectionReport>,
+    ) -> Self {
+        let mut metrics = RepairMetrics::default();
+        for collection in &collections {
+            metrics.merge(&collection.metrics);
+        }
+        metrics.elapsed = elapsed;
+        Self {
+            database: database.into(),
+            encoding: options.encoding,
+            dry_run: options.dry_run,
+            started_at,
+            metrics,
+            collections,
+        }
+    }
+
+    /// Collections that were repaired completely.
+    pub fn finished_collections(&self) -> impl Iterator<Item = &CollectionReport> {
+        self.collections.iter().filter(|collection| {
+            collection.metrics.collections_timed_out == 0
+                && collection.metrics.collections_aborted == 0
+        })
+    }
+
+    /// Collections abandoned after [`RepairOptions::max_errors_per_collection`] documents failed.
+    pub fn partial_collections(&self) -> impl Iterator<Item = &CollectionReport> {
+        self.collections
+            .iter()
+            .filter(|collection| collection.metrics.collections_aborted > 0)
+    }
+
+    /// Collections abandoned after exceeding [`RepairOptions::collection_timeout`].
+    pub fn timed_out_collections(&self) -> impl Iterator<Item = &CollectionReport> {
+        self.collections
+            .iter()
+            .filter(|collection| collection.metrics.collections_timed_out > 0)
+    }
+
+    /// The `n` most frequently repaired field paths, most frequent first.
+    #[must_use]
+    pub fn top_fields(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for change in self
+            .collections
+            .iter()
+            .flat_map(|collection| &collection.changes)
+            .flat_map(|document| &document.field_changes)
+        {
+            *counts.entry(change.path.as_str()).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts.truncate(n);
+        counts
+    }
+
+    /// Up to `n` example changes per repaired field path, ordered by path.
+    #[must_use]
+    pub fn field_samples(&self, n: usize) -> Vec<(&str, Vec<&FieldChange>)> {
+        let mut samples: BTreeMap<&str, Vec<&FieldChange>> = BTreeMap::new();
+        for change in self
+            .collections
+            .iter()
+            .flat_map(|collection| &collection.changes)
+            .flat_map(|document| &document.field_changes)
+        {
+            let examples = samples.entry(change.path.as_str()).or_default();
+            if examples.len() < n {
+                examples.push(change);
+            }
+        }
+        samples.into_iter().collect()
+    }
+
+    /// Render the report as Markdown, listing the `top_n` most frequently broken fields.
+    #[must_use]
+    pub fn to_markdown(&self, top_n: usize) -> String {
+        let mut out = String::new();
+        self.write_markdown(&mut out, top_n)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    fn write_markdown(&self, out: &mut impl Write, top_n: usize) -> fmt::Result {
+        writeln!(out, "# UTF-8 repair report")?;
+        writeln!(out)?;
+        writeln!(out, "- **Database:** `{}`", self.database)?;
+        writeln!(
+            out,
+            "- **Started:** {}",
+            self.started_at.try_to_rfc3339_string().unwrap_or_default()
+        )?;
+        writeln!(out, "- **Encoding:** `{}`", self.encoding)?;
+        writeln!(out, "- **Dry run:** {}", self.dry_run)?;
+        writeln!(out, "- **Documents:** {}", self.metrics.total_docs)?;
+        writeln!(out, "- **Changed:** {}", self.metrics.changed_docs)?;
+        writeln!(out, "- **Errors:** {}", self.metrics.error_docs)?;
+        writeln!(out, "- **Skipped:** {}", self.metrics.skipped_docs)?;
+        writeln!(
+            out,
+            "- **Fields skipped (parse error):** {}",
+            self.metrics.parse_error_fields
+        )?;
+        writeln!(
+            out,
+            "- **Fields degraded (lossy UTF-8 kept):** {}",
+            self.metrics.degraded_fields
+        )?;
+        writeln!(
+            out,
+            "- **Verification failures:** {}",
+            self.metrics.verification_failures
+        )?;
+        writeln!(out, "- **Elapsed:** {:.2?}", self.metrics.elapsed)?;
+        writeln!(out)?;
+        writeln!(out, "## Collections")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "| collection | scanned | changed | fields fixed | elapsed |"
+        )?;
+        writeln!(out, "| --- | ---: | ---: | ---: | ---: |")?;
+        for collection in self.finished_collections() {
+            writeln!(
+                out,
+                "| `{}` | {} | {} | {} | {:.2?} |",
+                collection.collection,
+                collection.metrics.total_docs,
+                collection.metrics.changed_docs,
+                collection.metrics.total_fields_fixed,
+                collection.metrics.elapsed
+            )?;
+        }
+        let timed_out: Vec<_> = self.timed_out_collections().collect();
+        if !timed_out.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "## Timed out collections")?;
+            writeln!(out)?;
+            for collection in timed_out {
+                writeln!(
+                    out,
+                    "- `{}` after {:.2?}",
+                    collection.collection, collection.metrics.elapsed
+                )?;
+            }
+        }
+        let partial: Vec<_> = self.partial_collections().collect();
+        if !partial.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "## Partially processed collections")?;
+            writeln!(out)?;
+            for collection in partial {
+                writeln!(
+                    out,
+                    "- `{}` stopped after {} errors in {} documents",
+                    collection.collection,
+                    collection.metrics.error_docs,
+                    collection.metrics.total_docs
+                )?;
+            }
+        }
+        let top_fields = self.top_fields(top_n);
+        if !top_fields.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "## Most frequently broken fields")?;
+            writeln!(out)?;
+            writeln!(out, "| field | count |")?;
+            writeln!(out, "| --- | ---: |")?;
+            for (field, count) in top_fields {
+                writeln!(out, "| `{field}` | {count} |")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for collection in self.timed_out_collections() {
+            writeln!(
+                f,
+                "collection = {: <20} TIMED OUT after {:.2?}",
+                collection.collection, collection.metrics.elapsed
+            )?;
+        }
+        for collection in self.partial_collections() {
+            writeln!(
+                f,
+                "collection = {: <20} ABORTED after {} errors in {} documents",
+                collection.collection, collection.metrics.error_docs, collection.metrics.total_docs
+            )?;
+        }
+        for collection in self.finished_collections() {
+            writeln!(
+                f,
+                "collection = {: <20} scanned = {: <10} changed = {: <10} fields fixed = {: <10} elapsed = {:.2?}",
+                collection.collection,
+                collection.metrics.total_docs,
+                collection.metrics.changed_docs,
+                collection.metrics.total_fields_fixed,
+                collection.metrics.elapsed
+            )?;
+        }
+        writeln!(f, "{}", self.metrics)
+    }
+}
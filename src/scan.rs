@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use mongodb::{bson, ClientSession, SessionCursor};
+
+use crate::RepairOptions;
+
+/// Broken documents of a collection, found by the first pass of
+/// [`RepairOptions::two_pass_scan`](crate::RepairOptions::two_pass_scan).
+#[derive(Debug, Default)]
+pub(crate) struct BrokenIndex {
+    /// Paths of the broken fields by collection and hex `_id`.
+    pub(crate) fields: HashMap<(String, String), Vec<String>>,
+    /// Ids of the broken documents, in the order they were found.
+    ids: Vec<bson::oid::ObjectId>,
+}
+
+/// Collect the paths of the string values and field names of `doc` that are not valid UTF-8.
+///
+/// A broken field name stops the iteration of its document, which is recorded instead.
+fn broken_fields(
+    doc: &bson::RawDocument,
+    path: &str,
+    fields: &mut Vec<String>,
+) -> crate::Result<()> {
+    for elem in doc.iter_elements() {
+        let elem = match elem {
+            Ok(elem) => elem,
+            Err(
+                err @ bson::raw::Error {
+                    kind: bson::raw::ErrorKind::Utf8EncodingError(_),
+                    ..
+                },
+            ) if err.key().is_none() => {
+                fields.push(path.to_string());
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let field_path = match path {
+            "" => elem.key().to_string(),
+            _ => format!("{path}.{}", elem.key()),
+        };
+        match elem.value() {
+            Ok(bson::RawBsonRef::Document(doc)) => broken_fields(doc, &field_path, fields)?,
+            Ok(bson::RawBsonRef::Array(items)) => {
+                let items = bson::RawDocument::from_bytes(items.as_bytes())?;
+                for elem in items.iter_elements() {
+                    let elem = elem?;
+                    let item_path = format!("{field_path}[{}]", elem.key());
+                    match elem.value() {
+                        Ok(bson::RawBsonRef::Document(doc)) => {
+                            broken_fields(doc, &item_path, fields)?;
+                        }
+                        Ok(_) => {}
+                        Err(_) => fields.push(item_path),
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => fields.push(field_path),
+        }
+    }
+    Ok(())
+}
+
+/// Query for the documents of `collection` with the `ids`.
+fn find_ids(
+    collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    ids: Vec<bson::oid::ObjectId>,
+    projection: Option<bson::Document>,
+    max_time: Option<std::time::Duration>,
+) -> mongodb::action::Find<'_, bson::RawDocumentBuf> {
+    let mut find = collection.find(bson::doc! {"_id": {"$in": ids}});
+    if let Some(projection) = projection {
+        find = find.projection(projection);
+    }
+    if let Some(max_time) = max_time {
+        find = find.max_time(max_time);
+    }
+    find
+}
+
+impl BrokenIndex {
+    /// Read every document of `collection` and index the broken ones,
+    /// in the snapshot `session` if given.
+    pub(crate) async fn scan(
+        collection: &mongodb::Collection<bson::RawDocumentBuf>,
+        options: &RepairOptions,
+        session: Option<&mut ClientSession>,
+    ) -> crate::Result<Self> {
+        let mut find = collection.find(bson::doc! {});
+        if let Some(hint) = &options.hint {
+            find = find.hint(mongodb::options::Hint::Keys(hint.clone()));
+        }
+        if let Some(max_time) = options.cursor_max_time {
+            find = find.max_time(max_time);
+        }
+        let mut index = Self::default();
+        let mut index_document = |doc: bson::RawDocumentBuf| -> crate::Result<()> {
+            let mut fields = Vec::new();
+            broken_fields(&doc, "", &mut fields)?;
+            if fields.is_empty() {
+                return Ok(());
+            }
+            let id = doc.get_object_id("_id")?;
+            index.ids.push(id);
+            index
+                .fields
+                .insert((collection.name().to_string(), id.to_hex()), fields);
+            Ok(())
+        };
+        if let Some(session) = session {
+            let mut cursor = find.session(&mut *session).await?;
+            while let Some(doc) = cursor.next(session).await.transpose()? {
+                index_document(doc)?;
+            }
+        } else {
+            if let Some(read_concern) = &options.read_concern {
+                find = find.read_concern(read_concern.clone());
+            }
+            let mut cursor = find.await?;
+            while let Some(doc) = cursor.try_next().await? {
+                index_document(doc)?;
+            }
+        }
+        let field_count: usize = index.fields.values().map(Vec::len).sum();
+        println!(
+            "collection = {: <20} SCANNED {} broken documents with {field_count} broken fields",
+            collection.name(),
+            index.len()
+        );
+        Ok(index)
+    }
+
+    /// Number of broken documents.
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Read the broken documents from `collection`, [`RepairOptions::bulk_find_batch`]
+    /// ids per `$in` query, in the snapshot `session` if given.
+    pub(crate) fn documents<'a>(
+        &self,
+        collection: &mongodb::Collection<bson::RawDocumentBuf>,
+        options: &RepairOptions,
+        session: Option<&'a mut ClientSession>,
+    ) -> BoxStream<'a, mongodb::error::Result<bson::RawDocumentBuf>> {
+        let batches: Vec<Vec<bson::oid::ObjectId>> = self
+            .ids
+            .chunks(options.bulk_find_batch)
+            .map(<[_]>::to_vec)
+            .collect();
+        let collection = collection.clone();
+        let projection = options.projection.clone();
+        let max_time = options.cursor_max_time;
+        if let Some(session) = session {
+            let state = (session, batches.into_iter(), None::<SessionCursor<_>>);
+            return stream::try_unfold(state, move |(session, mut batches, mut cursor)| {
+                let collection = collection.clone();
+                let projection = projection.clone();
+                async move {
+                    loop {
+                        if let Some(batch) = cursor.as_mut() {
+                            if let Some(doc) = batch.next(session).await.transpose()? {
+                                return Ok(Some((doc, (session, batches, cursor))));
+                            }
+                        }
+                        let Some(ids) = batches.next() else {
+                            return Ok(None);
+                        };
+                        let find = find_ids(&collection, ids, projection.clone(), max_time);
+                        cursor = Some(find.session(&mut *session).await?);
+                    }
+                }
+            })
+            .boxed();
+        }
+        let read_concern = options.read_concern.clone();
+        stream::iter(batches)
+            .then(move |ids| {
+                let collection = collection.clone();
+                let projection = projection.clone();
+                let read_concern = read_concern.clone();
+                async move {
+                    let mut find = find_ids(&collection, ids, projection, max_time);
+                    if let Some(read_concern) = read_concern {
+                        find = find.read_concern(read_concern);
+                    }
+                    find.await
+                }
+            })
+            .try_flatten()
+            .boxed()
+    }
+}
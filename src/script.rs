@@ -0,0 +1,135 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rhai::packages::{Package, StandardPackage};
+use rhai::{Dynamic, Engine, EvalAltResult, Module, Scope, Shared, AST};
+
+use crate::RepairError;
+
+/// [Rhai](https://rhai.rs) script repairing string values, for [`Encoding::Script`](crate::Encoding::Script).
+///
+/// The script runs once per value, with the raw bytes of the value as the blob
+/// `old_bytes` and its dot-notation `field_path` in scope, and evaluates to the
+/// repaired string. Scripts running longer than the timeout are aborted.
+///
+/// ```
+/// # fn main() -> color_eyre::eyre::Result<()> {
+/// use mongo_repair_utf8::testing::make_corrupt_doc;
+/// use mongo_repair_utf8::{fix_document, Encoding, RepairOptions, TransformScript};
+/// use mongodb::bson;
+///
+/// let script = TransformScript::new(
+///     r#"
+///     let fixed = blob();
+///     for i in 0..old_bytes.len() {
+///         let byte = old_bytes[i];
+///         if byte == 0xe9 { fixed.append("é") } else { fixed.push(byte) }
+///     }
+///     fixed.as_string()
+///     "#,
+/// )?;
+/// let doc = make_corrupt_doc("name", b"caf\xe9");
+/// let options = RepairOptions::default()
+///     .encoding(Encoding::Script)
+///     .transform_script(script)
+///     .build();
+/// let mut new_doc = bson::RawDocumentBuf::new();
+/// let changes = fix_document(&doc, &mut new_doc, &options, &mut Vec::new())?;
+/// assert_eq!(changes[0].new_value, "café");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TransformScript {
+    ast: AST,
+    package: Shared<Module>,
+    timeout: Duration,
+}
+
+impl TransformScript {
+    /// Compile the script `source`, with a timeout of one second.
+    pub fn new(source: &str) -> crate::Result<Self> {
+        let ast = Engine::new()
+            .compile(source)
+            .map_err(|err| RepairError::Other(format!("invalid transform script: {err}")))?;
+        Ok(Self {
+            ast,
+            package: StandardPackage::new().as_shared_module(),
+            timeout: Duration::from_secs(1),
+        })
+    }
+
+    /// Compile the script at `path`, with a timeout of one second.
+    pub fn from_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)?;
+        Self::new(&source).map_err(|err| RepairError::Other(format!("{}: {err}", path.display())))
+    }
+
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Repair the `old_bytes` of the value at `field_path`.
+    pub(crate) fn transform(&self, old_bytes: &[u8], field_path: &str) -> crate::Result<String> {
+        let failed = |reason: String| RepairError::EncodingFailed {
+            field: field_path.to_string(),
+            reason,
+        };
+        let mut engine = Engine::new_raw();
+        engine.register_global_module(self.package.clone());
+        let started = Instant::now();
+        let timeout = self.timeout;
+        engine.on_progress(move |_| (started.elapsed() > timeout).then_some(Dynamic::UNIT));
+        let mut scope = Scope::new();
+        scope.push("old_bytes", Dynamic::from_blob(old_bytes.to_vec()));
+        scope.push("field_path", field_path.to_string());
+        engine
+            .eval_ast_with_scope::<String>(&mut scope, &self.ast)
+            .map_err(|err| match *err {
+                EvalAltResult::ErrorTerminated(..) => failed(format!(
+                    "transform script timed out after {:.2?}",
+                    self.timeout
+                )),
+                err => failed(format!("transform script failed: {err}")),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_has_old_bytes_and_field_path() {
+        let script = TransformScript::new(r#"`${field_path}:${old_bytes.len()}`"#).unwrap();
+        assert_eq!(script.transform(b"caf\xe9", "a.b").unwrap(), "a.b:4");
+    }
+
+    #[test]
+    fn non_string_result_fails() {
+        let script = TransformScript::new("old_bytes.len()").unwrap();
+        assert!(matches!(
+            script.transform(b"x", "name"),
+            Err(RepairError::EncodingFailed { field, .. }) if field == "name"
+        ));
+    }
+
+    #[test]
+    fn endless_script_times_out() {
+        let script = TransformScript::new("loop {}")
+            .unwrap()
+            .timeout(Duration::from_millis(10));
+        let Err(RepairError::EncodingFailed { reason, .. }) = script.transform(b"x", "name") else {
+            panic!("expected the script to time out");
+        };
+        assert!(reason.contains("timed out"), "{reason}");
+    }
+
+    #[test]
+    fn syntax_error_fails_to_compile() {
+        assert!(TransformScript::new("let =").is_err());
+    }
+}
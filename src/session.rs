@@ -0,0 +1,172 @@
+use std::time::Instant;
+
+use futures::stream::{self, StreamExt};
+use mongodb::bson;
+
+use crate::hook::run_hook;
+use crate::{
+    fix_collection, CollectionReport, RepairError, RepairMetrics, RepairOptions, RunReport,
+};
+
+/// Repairs the collections of a database and accumulates their reports.
+///
+/// ```no_run
+/// use mongo_repair_utf8::{RepairOptions, RepairSession};
+/// use mongodb::Client;
+///
+/// # async fn run() -> color_eyre::eyre::Result<()> {
+/// let client = Client::with_uri_str("mongodb://localhost:27017").await?;
+/// let options = RepairOptions::default().dry_run(true).concurrency(4).build();
+/// let mut session = RepairSession::new(client.database("app"), options);
+/// let metrics = session.repair_database(&[]).await?;
+/// println!("{metrics}");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RepairSession {
+    db: mongodb::Database,
+    options: RepairOptions,
+    started: Instant,
+    started_at: bson::DateTime,
+    collections: Vec<CollectionReport>,
+}
+
+impl RepairSession {
+    #[must_use]
+    pub fn new(db: mongodb::Database, options: RepairOptions) -> Self {
+        Self {
+            db,
+            options,
+            started: Instant::now(),
+            started_at: bson::DateTime::now(),
+            collections: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &RepairOptions {
+        &self.options
+    }
+
+    /// Repair `collection_names`, or every collection of the database if empty.
+    ///
+    /// With [`RepairOptions::start_from_collection`] or [`RepairOptions::stop_after_collection`],
+    /// the collections are repaired in alphabetical order, limited to that range.
+    ///
+    /// Otherwise, they are repaired in the [`RepairOptions::collection_order`].
+    /// The [`RepairOptions::prioritize_collections`] are repaired first. Collections
+    /// exceeding the [`RepairOptions::collection_timeout`] are abandoned and
+    /// reported as timed out. The [`RepairOptions::pre_repair_hook`] and
+    /// [`RepairOptions::post_repair_hook`] run before and after each collection;
+    /// a failing pre-repair hook fails the collection.
+    ///
+    /// Collections that fail are logged and skipped, unless
+    /// [`RepairOptions::abort_on_error`] is set, in which case the run stops at the
+    /// first failed collection, cancelling the others, and its error is returned.
+    /// Returns the metrics of the repaired collections.
+    pub async fn repair_database(
+        &mut self,
+        collection_names: &[String],
+    ) -> crate::Result<RepairMetrics> {
+        let mut collection_names: Vec<String> = if !collection_names.is_empty() {
+            collection_names.to_vec()
+        } else {
+            self.db.list_collection_names().await?
+        };
+        let start = self.options.start_from_collection.as_deref();
+        let stop = self.options.stop_after_collection.as_deref();
+        if start.is_some() || stop.is_some() {
+            // resuming relies on a stable order
+            collection_names.sort();
+            collection_names.retain(|name| {
+                start.is_none_or(|start| name.as_str() >= start)
+                    && stop.is_none_or(|stop| name.as_str() <= stop)
+            });
+        } else {
+            self.options
+                .collection_order
+                .sort(&self.db, &mut collection_names)
+                .await?;
+        }
+        let priorities = &self.options.prioritize_collections;
+        if !priorities.is_empty() {
+            // stable, so the other collections keep their order
+            collection_names.sort_by_key(|name| {
+                priorities
+                    .iter()
+                    .position(|priority| priority == name)
+                    .unwrap_or(priorities.len())
+            });
+        }
+
+        let started = Instant::now();
+        let mut results = stream::iter(collection_names)
+            .map(|col| {
+                let collection = self.db.collection::<bson::RawDocumentBuf>(&col);
+                let database = self.db.name();
+                let options = &self.options;
+                async move {
+                    if let Some(hook) = &options.pre_repair_hook {
+                        if !run_hook(hook, database, &col).await? {
+                            return Err(RepairError::Other(format!(
+                                "skipping collection {col} after the pre-repair hook failed"
+                            )));
+                        }
+                    }
+                    let result = match options.collection_timeout {
+                        None => fix_collection(collection, options).await,
+                        Some(timeout) => {
+                            let started = Instant::now();
+                            match tokio::time::timeout(timeout, fix_collection(collection, options))
+                                .await
+                            {
+                                Ok(result) => result,
+                                Err(_) => {
+                                    eprintln!(
+                                        "collection = {: <20} WARNING timed out after {timeout:.2?}, continuing with the next collection",
+                                        col
+                                    );
+                                    Ok(CollectionReport::timed_out(&col, started.elapsed()))
+                                }
+                            }
+                        }
+                    };
+                    if let Some(hook) = &options.post_repair_hook {
+                        run_hook(hook, database, &col).await?;
+                    }
+                    result
+                }
+            })
+            .buffered(self.options.concurrency);
+
+        let mut metrics = RepairMetrics::default();
+        while let Some(result) = results.next().await {
+            match result {
+                Ok(collection) => {
+                    metrics.merge(&collection.metrics);
+                    self.collections.push(collection);
+                }
+                // dropping the stream cancels the collections still in progress
+                Err(err) if self.options.abort_on_error => return Err(err),
+                // the other collections could not be confirmed either
+                Err(err @ RepairError::ConfirmationAborted) => return Err(err),
+                Err(err) => eprintln!("{err:?}"),
+            }
+        }
+        metrics.elapsed = started.elapsed();
+        Ok(metrics)
+    }
+
+    /// Finish the session, summarizing all collections repaired in it.
+    #[must_use]
+    pub fn into_report(self) -> RunReport {
+        RunReport::new(
+            self.db.name(),
+            &self.options,
+            self.started_at,
+            self.started.elapsed(),
+            self.collections,
+        )
+    }
+}
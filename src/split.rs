@@ -0,0 +1,258 @@
+use std::fmt;
+use std::str::FromStr;
+
+use mongodb::bson;
+
+use crate::RepairError;
+
+/// How to handle repaired documents that exceed [`RepairOptions::max_document_size`].
+///
+/// [`RepairOptions::max_document_size`]: crate::RepairOptions::max_document_size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplitStrategy {
+    /// Fail the collection.
+    Error,
+    /// Drop trailing items of the largest array field until the document fits.
+    Truncate,
+    /// Move trailing items of the largest array field into additional documents
+    /// with `_id` set to `<id>_part_<n>` and `_split_from` set to the original `_id`.
+    SplitArray,
+}
+
+impl SplitStrategy {
+    /// All supported strategies.
+    pub const ALL: [SplitStrategy; 3] = [
+        SplitStrategy::Error,
+        SplitStrategy::Truncate,
+        SplitStrategy::SplitArray,
+    ];
+
+    /// Human-readable name, as accepted by [`FromStr`].
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Truncate => "truncate",
+            Self::SplitArray => "split-array",
+        }
+    }
+}
+
+impl fmt::Display for SplitStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SplitStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|strategy| strategy.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|strategy| strategy.name()).collect();
+                format!(
+                    "unknown split strategy {s:?} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// An oversized document shrunk to fit the size limit.
+pub(crate) struct Split {
+    pub(crate) document: bson::RawDocumentBuf,
+    /// Documents holding the array items moved out of `document`.
+    pub(crate) parts: Vec<bson::RawDocumentBuf>,
+    /// Number of array items dropped (when truncating) or moved into `parts`.
+    pub(crate) moved: usize,
+}
+
+/// Size of an empty BSON array (length prefix and null terminator).
+const EMPTY_ARRAY_SIZE: usize = 4 + 1;
+
+/// Encoded size of `value` as a BSON element value.
+fn value_size(value: &bson::RawBson) -> usize {
+    let mut doc = bson::RawDocumentBuf::new();
+    doc.append("", value.clone());
+    // length prefix, element type, empty key and the two null terminators
+    doc.as_bytes().len() - (4 + 1 + 1 + 1)
+}
+
+/// Encoded size of an element with the given key and value size.
+fn element_size(key: &str, value_size: usize) -> usize {
+    1 + key.len() + 1 + value_size
+}
+
+/// Copy `doc`, replacing the top-level field `key` with `value` (or removing it if `None`).
+fn replace_field(
+    doc: &bson::RawDocument,
+    key: &str,
+    value: Option<bson::RawBson>,
+) -> crate::Result<bson::RawDocumentBuf> {
+    let mut value = value;
+    let mut new_doc = bson::RawDocumentBuf::new();
+    for elem in doc {
+        let (elem_key, elem_value) = elem?;
+        if elem_key != key {
+            new_doc.append(elem_key, elem_value.to_raw_bson());
+        } else if let Some(value) = value.take() {
+            new_doc.append(elem_key, value);
+        }
+    }
+    Ok(new_doc)
+}
+
+/// Move leading `items` into an array until its size would exceed `budget` bytes.
+fn take_chunk(
+    items: &mut std::iter::Peekable<impl Iterator<Item = (bson::RawBson, usize)>>,
+    budget: usize,
+) -> bson::RawArrayBuf {
+    let mut chunk = bson::RawArrayBuf::new();
+    let mut size = EMPTY_ARRAY_SIZE;
+    let mut idx = 0;
+    while let Some((_, item_size)) = items.peek() {
+        let item_size = element_size(&idx.to_string(), *item_size);
+        if size + item_size > budget {
+            break;
+        }
+        let Some((item, _)) = items.next() else {
+            break;
+        };
+        chunk.push(item);
+        size += item_size;
+        idx += 1;
+    }
+    chunk
+}
+
+/// Prefix of the `_id` of the parts split off a document with `_id` `id`:
+/// the hex of an object id, a string as is, or the extended JSON of any other value.
+fn part_id_prefix(id: &bson::RawBson) -> crate::Result<String> {
+    Ok(match id {
+        bson::RawBson::ObjectId(id) => id.to_hex(),
+        bson::RawBson::String(id) => id.clone(),
+        other => bson::Bson::try_from(other.clone())?
+            .into_relaxed_extjson()
+            .to_string(),
+    })
+}
+
+/// Shrink `doc` below `limit` bytes by truncating or splitting its largest array field.
+pub(crate) fn split_document(
+    doc: &bson::RawDocument,
+    limit: usize,
+    strategy: SplitStrategy,
+) -> crate::Result<Split> {
+    let mut largest: Option<(&str, &bson::RawArray, usize)> = None;
+    for elem in doc.iter_elements() {
+        let elem = elem?;
+        if let bson::raw::RawBsonRef::Array(array) = elem.value()? {
+            if largest.is_none_or(|(_, _, size)| elem.len() > size) {
+                largest = Some((elem.key(), array, elem.len()));
+            }
+        }
+    }
+    let Some((field, array, _)) = largest else {
+        return Err(RepairError::Other(
+            "document has no array field to split".to_string(),
+        ));
+    };
+
+    let items = array
+        .into_iter()
+        .map(|item| {
+            let item = item?.to_raw_bson();
+            let size = value_size(&item);
+            Ok((item, size))
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    let mut items = items.into_iter().peekable();
+
+    let base = replace_field(doc, field, None)?;
+    let budget = limit
+        .checked_sub(base.as_bytes().len() + element_size(field, 0))
+        .ok_or_else(|| {
+            RepairError::Other(format!(
+                "document exceeds the size limit even without {field}"
+            ))
+        })?;
+    let first = take_chunk(&mut items, budget);
+    let moved = items.len();
+    let document = replace_field(doc, field, Some(bson::RawBson::Array(first)))?;
+
+    let mut parts = Vec::new();
+    if strategy == SplitStrategy::SplitArray {
+        let id = doc
+            .get("_id")?
+            .ok_or_else(|| RepairError::Other("document has no _id".to_string()))?
+            .to_raw_bson();
+        let prefix = part_id_prefix(&id)?;
+        while items.peek().is_some() {
+            let mut part = bson::RawDocumentBuf::new();
+            part.append("_id", format!("{prefix}_part_{}", parts.len() + 1));
+            part.append("_split_from", id.clone());
+            let budget = limit.saturating_sub(part.as_bytes().len() + element_size(field, 0));
+            let chunk = take_chunk(&mut items, budget);
+            if chunk.is_empty() {
+                return Err(RepairError::Other(format!(
+                    "an item of {field} exceeds the size limit on its own"
+                )));
+            }
+            part.append(field, chunk);
+            parts.push(part);
+        }
+    }
+    Ok(Split {
+        document,
+        parts,
+        moved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oversized(id: impl Into<bson::Bson>) -> bson::RawDocumentBuf {
+        let items: Vec<_> = (0..100).map(|idx| format!("item {idx:>20}")).collect();
+        bson::RawDocumentBuf::from_document(&bson::doc! { "_id": id.into(), "items": items })
+            .unwrap()
+    }
+
+    #[test]
+    fn split_array_with_object_id() {
+        let id = bson::oid::ObjectId::new();
+        let doc = oversized(id);
+        let split = split_document(&doc, 1000, SplitStrategy::SplitArray).unwrap();
+        assert!(split.document.as_bytes().len() <= 1000);
+        assert!(!split.parts.is_empty());
+        for (idx, part) in split.parts.iter().enumerate() {
+            assert!(part.as_bytes().len() <= 1000);
+            let part_id = format!("{}_part_{}", id.to_hex(), idx + 1);
+            assert_eq!(part.get_str("_id").unwrap(), part_id);
+            assert_eq!(part.get_object_id("_split_from").unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn split_array_with_other_ids() {
+        let split = split_document(&oversized("user-1"), 1000, SplitStrategy::SplitArray).unwrap();
+        assert_eq!(split.parts[0].get_str("_id").unwrap(), "user-1_part_1");
+        assert_eq!(split.parts[0].get_str("_split_from").unwrap(), "user-1");
+
+        let split = split_document(&oversized(42), 1000, SplitStrategy::SplitArray).unwrap();
+        assert_eq!(split.parts[0].get_str("_id").unwrap(), "42_part_1");
+        assert_eq!(split.parts[0].get_i32("_split_from").unwrap(), 42);
+    }
+
+    #[test]
+    fn truncate_drops_items() {
+        let split = split_document(&oversized(1), 1000, SplitStrategy::Truncate).unwrap();
+        assert!(split.document.as_bytes().len() <= 1000);
+        assert!(split.parts.is_empty());
+        assert!(split.moved > 0);
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::{Encoding, RepairError};
+
+/// A string value that was decoded, recorded for [`FieldStats`].
+#[derive(Debug, Clone)]
+pub(crate) struct FieldRecord {
+    pub(crate) path: String,
+    pub(crate) encoding: Encoding,
+    /// Whether the repaired value was written, or rejected (e.g. not confirmed).
+    pub(crate) repaired: bool,
+    pub(crate) chars_changed: u64,
+}
+
+impl FieldRecord {
+    pub(crate) fn new(
+        path: &str,
+        encoding: Encoding,
+        repaired: bool,
+        old_value: &str,
+        new_value: &str,
+    ) -> Self {
+        let old_len = old_value.chars().count();
+        let new_len = new_value.chars().count();
+        let differing = old_value
+            .chars()
+            .zip(new_value.chars())
+            .filter(|(old, new)| old != new)
+            .count();
+        Self {
+            path: path.to_string(),
+            encoding,
+            repaired,
+            chars_changed: (differing + old_len.abs_diff(new_len)) as u64,
+        }
+    }
+}
+
+/// Counters of a single field path of a collection, decoded with one encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FieldCounts {
+    repaired: u64,
+    skipped: u64,
+    chars_changed: u64,
+}
+
+/// Per-field repair statistics, shared by concurrently repaired collections.
+#[derive(Debug, Default)]
+pub struct FieldStats {
+    counts: Mutex<HashMap<(String, String, Encoding), FieldCounts>>,
+}
+
+impl FieldStats {
+    /// Count the decoded values `records` of `collection`.
+    pub(crate) fn record(&self, collection: &str, records: &[FieldRecord]) -> crate::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut counts = self
+            .counts
+            .lock()
+            .map_err(|_| RepairError::Other("field stats are poisoned".to_string()))?;
+        for record in records {
+            let counts = counts
+                .entry((collection.to_string(), record.path.clone(), record.encoding))
+                .or_default();
+            if record.repaired {
+                counts.repaired += 1;
+                counts.chars_changed += record.chars_changed;
+            } else {
+                counts.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the statistics as CSV, one row per collection, field path and encoding,
+    /// most frequently repaired first.
+    pub fn write_csv(&self, writer: impl Write) -> crate::Result<()> {
+        let counts = self
+            .counts
+            .lock()
+            .map_err(|_| RepairError::Other("field stats are poisoned".to_string()))?;
+        let mut rows: Vec<_> = counts.iter().collect();
+        rows.sort_by(|a, b| b.1.repaired.cmp(&a.1.repaired).then(a.0.cmp(b.0)));
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record([
+            "collection",
+            "field_path",
+            "encoding_detected",
+            "count_repaired",
+            "count_skipped",
+            "total_chars_changed",
+        ])?;
+        for ((collection, path, encoding), counts) in rows {
+            writer.write_record([
+                collection.as_str(),
+                path.as_str(),
+                encoding.name(),
+                &counts.repaired.to_string(),
+                &counts.skipped.to_string(),
+                &counts.chars_changed.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_csv_quotes_fields() {
+        let stats = FieldStats::default();
+        let records = [
+            FieldRecord::new("name", Encoding::Utf16, true, "caf\u{fffd}", "café"),
+            FieldRecord::new("a,\"b\"", Encoding::Utf16, false, "x", "y"),
+        ];
+        stats.record("users", &records).unwrap();
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "collection,field_path,encoding_detected,count_repaired,count_skipped,total_chars_changed\n\
+             users,name,utf16,1,0,1\n\
+             users,\"a,\"\"b\"\"\",utf16,0,1,0\n"
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! Helpers for testing repairs without a database.
+
+use mongodb::bson;
+
+/// Document with a single string field `field` whose value bytes are `raw_bytes`,
+/// whether or not they are valid UTF-8.
+///
+/// The typed BSON API rejects invalid UTF-8, so the document is built byte by byte.
+///
+/// ```
+/// use mongo_repair_utf8::testing::make_corrupt_doc;
+///
+/// let doc = make_corrupt_doc("name", b"caf\xe9");
+/// assert!(doc.get_str("name").is_err());
+/// ```
+#[must_use]
+pub fn make_corrupt_doc(field: &str, raw_bytes: &[u8]) -> bson::RawDocumentBuf {
+    let value_len = i32::try_from(raw_bytes.len() + 1).expect("string value too long");
+    let mut bytes = vec![0; 4];
+    bytes.push(bson::spec::ElementType::String as u8);
+    bytes.extend_from_slice(field.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&value_len.to_le_bytes());
+    bytes.extend_from_slice(raw_bytes);
+    bytes.push(0);
+    bytes.push(0);
+    let len = i32::try_from(bytes.len()).expect("document too long");
+    bytes[..4].copy_from_slice(&len.to_le_bytes());
+    bson::RawDocumentBuf::from_bytes(bytes).expect("document is well-formed")
+}
@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Stage of processing a collection, timed for
+/// [`RepairOptions::timing_profile`](crate::RepairOptions::timing_profile).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    /// Running the query, until the first batch arrived.
+    CursorOpen,
+    /// Waiting for the cursor to yield the next document.
+    Fetch,
+    /// Repairing a document (including the second query of a projection).
+    Fix,
+    /// Writing a document, or its export.
+    Write,
+    /// Dropping the cursor.
+    CursorClose,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::CursorOpen,
+        Stage::Fetch,
+        Stage::Fix,
+        Stage::Write,
+        Stage::CursorClose,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::CursorOpen => "cursor open",
+            Self::Fetch => "fetch",
+            Self::Fix => "fix",
+            Self::Write => "write",
+            Self::CursorClose => "cursor close",
+        }
+    }
+}
+
+/// Durations of the [`Stage`]s of processing a collection,
+/// recorded by concurrently processed documents.
+#[derive(Debug, Default)]
+pub(crate) struct TimingProfile {
+    samples: Mutex<[Vec<Duration>; Stage::ALL.len()]>,
+}
+
+/// The `p`th percentile of the sorted `samples`, by nearest rank.
+fn percentile(samples: &[Duration], p: usize) -> Duration {
+    let rank = (samples.len() * p).div_ceil(100).max(1);
+    samples[rank - 1]
+}
+
+impl TimingProfile {
+    pub(crate) fn record(&self, stage: Stage, duration: Duration) {
+        // a poisoned lock only loses timings
+        if let Ok(mut samples) = self.samples.lock() {
+            samples[stage as usize].push(duration);
+        }
+    }
+
+    /// Print the p50, p95 and p99 latencies of every stage of `collection`.
+    pub(crate) fn print(&self, collection: &str) {
+        let Ok(mut samples) = self.samples.lock() else {
+            return;
+        };
+        println!("collection = {: <20} TIMING PROFILE", collection);
+        println!(
+            "  {: <12} {: >10} {: >12} {: >12} {: >12}",
+            "stage", "count", "p50", "p95", "p99"
+        );
+        for stage in Stage::ALL {
+            let samples = &mut samples[stage as usize];
+            if samples.is_empty() {
+                continue;
+            }
+            samples.sort_unstable();
+            println!(
+                "  {: <12} {: >10} {: >12} {: >12} {: >12}",
+                stage.name(),
+                samples.len(),
+                format!("{:.2?}", percentile(samples, 50)),
+                format!("{:.2?}", percentile(samples, 95)),
+                format!("{:.2?}", percentile(samples, 99)),
+            );
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use futures::TryStreamExt;
+use mongodb::bson;
+
+/// Re-reads replaced documents in batches and checks that they are now valid UTF-8,
+/// for [`RepairOptions::validate_post_repair`](crate::RepairOptions::validate_post_repair).
+///
+/// Ids are accumulated and looked up with a single `$in` query per batch.
+pub(crate) struct BatchVerifier {
+    batch_size: usize,
+    ids: Vec<bson::oid::ObjectId>,
+}
+
+impl BatchVerifier {
+    pub(crate) fn new(batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            ids: Vec::new(),
+        }
+    }
+
+    /// Queue the document `id` of `collection`, verifying the batch once it is full.
+    ///
+    /// Returns the number of documents that failed verification.
+    pub(crate) async fn push(
+        &mut self,
+        collection: &mongodb::Collection<bson::RawDocumentBuf>,
+        id: bson::oid::ObjectId,
+    ) -> crate::Result<u64> {
+        self.ids.push(id);
+        if self.ids.len() < self.batch_size {
+            return Ok(0);
+        }
+        self.flush(collection).await
+    }
+
+    /// Verify the queued documents of `collection`.
+    ///
+    /// Returns the number of documents that are missing or still not valid UTF-8.
+    pub(crate) async fn flush(
+        &mut self,
+        collection: &mongodb::Collection<bson::RawDocumentBuf>,
+    ) -> crate::Result<u64> {
+        if self.ids.is_empty() {
+            return Ok(0);
+        }
+        let ids = std::mem::take(&mut self.ids);
+        let mut missing: std::collections::HashSet<_> = ids.iter().copied().collect();
+        let mut failures = 0;
+        let mut cursor = collection
+            .find(bson::doc! {"_id": {"$in": ids}})
+            .batch_size(u32::try_from(self.batch_size).unwrap_or(u32::MAX))
+            .await?;
+        while let Some(doc) = cursor.try_next().await? {
+            let id = doc.get_object_id("_id")?;
+            missing.remove(&id);
+            if let Err(err) = bson::Document::try_from(doc.as_ref()) {
+                eprintln!(
+                    "collection = {: <20} id = {: <30} INVALID after repair ({err})",
+                    collection.name(),
+                    id.to_hex()
+                );
+                failures += 1;
+            }
+        }
+        for id in missing {
+            eprintln!(
+                "collection = {: <20} id = {: <30} NOT FOUND after repair",
+                collection.name(),
+                id.to_hex()
+            );
+            failures += 1;
+        }
+        Ok(failures)
+    }
+}